@@ -0,0 +1,95 @@
+use crossterm::{event, terminal, tty::IsTty};
+use std::io::{self, BufRead};
+
+/// `InputSource` is a trait used by [Repl](crate::Repl) to get the events it should respond to.
+///
+/// By default, [`TerminalInput`] is used, which reads events from the terminal. Implementing this
+/// allows the `Repl` to be driven by anything else, for example a list of scripted key presses,
+/// a recorded session or a socket.
+pub trait InputSource {
+    /// Blocks until the next event is available and returns it.
+    fn read(&mut self) -> crate::Result<event::Event>;
+
+    /// Whether the source is interactive. If it is not, whole lines are read with
+    /// [`read_line`](InputSource::read_line) instead of key events, and nothing is rendered.
+    fn is_interactive(&self) -> bool {
+        true
+    }
+
+    /// Reads a line into `buf` the same way as [`BufRead::read_line`], returning `0` once there is no
+    /// more input. This is only used if the source is not interactive.
+    fn read_line(&mut self, _buf: &mut String) -> io::Result<usize> {
+        Ok(0)
+    }
+
+    /// Called before the `Repl` starts reading events. Terminal based sources should put the
+    /// terminal in raw mode here, so that key presses are not buffered or echoed.
+    fn enable_raw_mode(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    /// Called once the `Repl` has finished reading events, and should undo whatever was done in
+    /// [`enable_raw_mode`](InputSource::enable_raw_mode).
+    fn disable_raw_mode(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+}
+
+/// The default [`InputSource`], which reads events from the terminal using crossterm.
+///
+/// If `stdin` is not a terminal, for example when input is piped in, it is not interactive and
+/// lines are read from `stdin` instead.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TerminalInput;
+
+impl InputSource for TerminalInput {
+    fn read(&mut self) -> crate::Result<event::Event> {
+        event::read()
+    }
+
+    fn is_interactive(&self) -> bool {
+        io::stdin().is_tty()
+    }
+
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        io::stdin().read_line(buf)
+    }
+
+    fn enable_raw_mode(&mut self) -> crate::Result<()> {
+        terminal::enable_raw_mode()
+    }
+
+    fn disable_raw_mode(&mut self) -> crate::Result<()> {
+        terminal::disable_raw_mode()
+    }
+}
+
+/// A non interactive [`InputSource`] which reads lines from any [`BufRead`], for example a file.
+#[derive(Debug)]
+pub struct LineInput<R: BufRead> {
+    reader: R,
+}
+
+impl<R: BufRead> LineInput<R> {
+    /// Create an input which reads lines from `reader`
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+}
+
+impl<R: BufRead> InputSource for LineInput<R> {
+    fn read(&mut self) -> crate::Result<event::Event> {
+        Err(crossterm::ErrorKind::IoError(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Events cannot be read from a LineInput",
+        )))
+    }
+
+    fn is_interactive(&self) -> bool {
+        false
+    }
+
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        self.reader.read_line(buf)
+    }
+}
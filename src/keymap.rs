@@ -0,0 +1,283 @@
+use crate::Editor;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// A function which is run when a key bound to [`Action::Custom`] is pressed.
+///
+/// It is given an [`Editor`] to read and change the command being edited, and the action it gives
+/// back, if any, is run afterwards.
+pub type Callback = Rc<dyn Fn(&mut Editor) -> Option<Action>>;
+
+/// Something the [Repl](crate::Repl) can do when a key is pressed.
+#[derive(Clone)]
+pub enum Action {
+    /// Insert the character at the cursor
+    Insert(char),
+    /// Insert 4 spaces at the cursor
+    Indent,
+    /// Complete the text at the cursor with [`LangInterface::complete`], or select the next
+    /// candidate in the completion menu. In the indentation of a line, this is the same as
+    /// [`Indent`](Action::Indent)
+    ///
+    /// [`LangInterface::complete`]: crate::LangInterface::complete
+    Complete,
+    /// Select the previous candidate in the completion menu
+    CompleteBackward,
+    /// Give the command if it is complete according to [`LangInterface::get_indent`], otherwise
+    /// add a new line
+    ///
+    /// [`LangInterface::get_indent`]: crate::LangInterface::get_indent
+    Enter,
+    /// Move the cursor one character left
+    Left,
+    /// Move the cursor one character right, or accept the suggestion at the end of the input
+    Right,
+    /// Move the cursor up a line, or to the previous history entry on the first line
+    Up,
+    /// Move the cursor down a line, or to the next history entry on the last line
+    Down,
+    /// Move the cursor to the start of the line
+    LineStart,
+    /// Move the cursor to the end of the line, or accept the suggestion at the end of the input
+    LineEnd,
+    /// Move the cursor to the start of the word before it
+    WordLeft,
+    /// Move the cursor to the end of the word after it
+    WordRight,
+    /// Search backward through history for the text which is typed next, or go to the next older
+    /// match while searching
+    SearchBackward,
+    /// Search forward through history for the text which is typed next, or go to the next newer
+    /// match while searching
+    SearchForward,
+    /// Go to the previous history entry, keeping the cursor on the same line
+    HistoryUp,
+    /// Go to the next history entry, keeping the cursor on the same line
+    HistoryDown,
+    /// Delete the character before the cursor, joining the line with the previous one at its start
+    Backspace,
+    /// Delete the character under the cursor, joining the next line with this one at its end
+    Delete,
+    /// Kill the word before the cursor
+    KillWordBackward,
+    /// Kill the word after the cursor
+    KillWordForward,
+    /// Kill the text from the start of the line to the cursor
+    KillLineBackward,
+    /// Kill the text from the cursor to the end of the line
+    KillLineForward,
+    /// Insert the most recently killed text
+    Yank,
+    /// Replace the text which was just yanked with the text killed before it
+    YankPop,
+    /// Undo the last edit
+    Undo,
+    /// Redo the last undone edit
+    Redo,
+    /// Clear the screen, keeping the current input
+    ClearScreen,
+    /// Discard the input and give [`Signal::Interrupted`](crate::Signal::Interrupted), or exit
+    /// depending on the [`InterruptPolicy`](crate::InterruptPolicy)
+    Interrupt,
+    /// Give [`Signal::Eof`](crate::Signal::Eof) on an empty prompt, otherwise delete the character
+    /// under the cursor
+    Eof,
+    /// Run a function. See [`Callback`]
+    Custom(Callback),
+}
+
+impl Action {
+    /// Create a [`Action::Custom`] from a function
+    pub fn custom<F: Fn(&mut Editor) -> Option<Action> + 'static>(f: F) -> Self {
+        Action::Custom(Rc::new(f))
+    }
+}
+
+impl fmt::Debug for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Insert(chr) => f.debug_tuple("Insert").field(chr).finish(),
+            Action::Indent => f.write_str("Indent"),
+            Action::Complete => f.write_str("Complete"),
+            Action::CompleteBackward => f.write_str("CompleteBackward"),
+            Action::Enter => f.write_str("Enter"),
+            Action::Left => f.write_str("Left"),
+            Action::Right => f.write_str("Right"),
+            Action::Up => f.write_str("Up"),
+            Action::Down => f.write_str("Down"),
+            Action::LineStart => f.write_str("LineStart"),
+            Action::LineEnd => f.write_str("LineEnd"),
+            Action::WordLeft => f.write_str("WordLeft"),
+            Action::WordRight => f.write_str("WordRight"),
+            Action::SearchBackward => f.write_str("SearchBackward"),
+            Action::SearchForward => f.write_str("SearchForward"),
+            Action::HistoryUp => f.write_str("HistoryUp"),
+            Action::HistoryDown => f.write_str("HistoryDown"),
+            Action::Backspace => f.write_str("Backspace"),
+            Action::Delete => f.write_str("Delete"),
+            Action::KillWordBackward => f.write_str("KillWordBackward"),
+            Action::KillWordForward => f.write_str("KillWordForward"),
+            Action::KillLineBackward => f.write_str("KillLineBackward"),
+            Action::KillLineForward => f.write_str("KillLineForward"),
+            Action::Yank => f.write_str("Yank"),
+            Action::YankPop => f.write_str("YankPop"),
+            Action::Undo => f.write_str("Undo"),
+            Action::Redo => f.write_str("Redo"),
+            Action::ClearScreen => f.write_str("ClearScreen"),
+            Action::Interrupt => f.write_str("Interrupt"),
+            Action::Eof => f.write_str("Eof"),
+            Action::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Maps keys to the [`Action`]s a [Repl](crate::Repl) runs when they are pressed.
+///
+/// If a key with modifiers is not bound, it is handled like the same key without modifiers, and
+/// characters which are not bound are inserted. So unbinding `Ctrl-Left` makes it move a single
+/// character like `Left`, and unbinding `Tab` makes it do nothing.
+///
+/// The default keymap has readline style bindings:
+///
+/// | Key                              | Action                       |
+/// |----------------------------------|------------------------------|
+/// | `Enter`                          | [`Enter`](Action::Enter)     |
+/// | `Tab`, `Shift-Tab`               | [`Complete`](Action::Complete), [`CompleteBackward`](Action::CompleteBackward) |
+/// | `Left`, `Right`, `Up`, `Down`    | move the cursor              |
+/// | `Ctrl-F`                         | [`Right`](Action::Right)     |
+/// | `Home`, `End`                    | [`LineStart`](Action::LineStart), [`LineEnd`](Action::LineEnd) |
+/// | `Ctrl-Left`, `Alt-B`             | [`WordLeft`](Action::WordLeft) |
+/// | `Ctrl-Right`, `Alt-F`            | [`WordRight`](Action::WordRight) |
+/// | `PageUp`, `PageDown`             | [`HistoryUp`](Action::HistoryUp), [`HistoryDown`](Action::HistoryDown) |
+/// | `Ctrl-R`, `Ctrl-S`               | [`SearchBackward`](Action::SearchBackward), [`SearchForward`](Action::SearchForward) |
+/// | `Backspace`, `Delete`            | delete a character           |
+/// | `Ctrl-W`, `Alt-Backspace`        | [`KillWordBackward`](Action::KillWordBackward) |
+/// | `Alt-D`                          | [`KillWordForward`](Action::KillWordForward) |
+/// | `Ctrl-U`                         | [`KillLineBackward`](Action::KillLineBackward) |
+/// | `Ctrl-K`                         | [`KillLineForward`](Action::KillLineForward) |
+/// | `Ctrl-Y`, `Alt-Y`                | [`Yank`](Action::Yank), [`YankPop`](Action::YankPop) |
+/// | `Ctrl-Z`, `Ctrl-_`               | [`Undo`](Action::Undo)       |
+/// | `Alt-/`                          | [`Redo`](Action::Redo)       |
+/// | `Ctrl-L`                         | [`ClearScreen`](Action::ClearScreen) |
+/// | `Ctrl-C`                         | [`Interrupt`](Action::Interrupt) |
+/// | `Ctrl-D`                         | [`Eof`](Action::Eof)         |
+///
+/// ```
+/// use shelp::{Action, Keymap};
+/// use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+///
+/// let mut keymap = Keymap::default();
+/// // PageUp no longer goes through history
+/// keymap.unbind(KeyEvent::new(KeyCode::PageUp, KeyModifiers::NONE));
+/// // Ctrl-A and Ctrl-E move to the start and end of the line
+/// keymap.bind(KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL), Action::LineStart);
+/// keymap.bind(KeyEvent::new(KeyCode::Char('e'), KeyModifiers::CONTROL), Action::LineEnd);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: HashMap<KeyEvent, Action>,
+}
+
+impl Keymap {
+    /// Create a keymap without any bindings. Characters are still inserted, but nothing else can
+    /// be done until keys are bound.
+    pub fn new() -> Self {
+        Self {
+            bindings: HashMap::new(),
+        }
+    }
+
+    /// Bind a key to an action, giving back the action it was bound to before
+    pub fn bind(&mut self, key: KeyEvent, action: Action) -> Option<Action> {
+        self.bindings.insert(key, action)
+    }
+
+    /// Remove the binding of a key, giving back the action it was bound to
+    pub fn unbind(&mut self, key: KeyEvent) -> Option<Action> {
+        self.bindings.remove(&key)
+    }
+
+    /// The action a key is bound to
+    pub fn get(&self, key: &KeyEvent) -> Option<&Action> {
+        self.bindings.get(key)
+    }
+
+    /// Remove the bindings of every key which runs an action matching `f`
+    pub fn unbind_where<F: FnMut(&Action) -> bool>(&mut self, mut f: F) {
+        self.bindings.retain(|_, action| !f(action));
+    }
+
+    /// The action which should be run when a key is pressed
+    pub(crate) fn action(&self, key: KeyEvent) -> Option<Action> {
+        if let Some(action) = self.get(&key) {
+            return Some(action.clone());
+        }
+
+        if !key.modifiers.is_empty() {
+            let plain = KeyEvent::new(key.code, KeyModifiers::NONE);
+            if let Some(action) = self.get(&plain) {
+                return Some(action.clone());
+            }
+        }
+
+        match key.code {
+            KeyCode::Char(chr) => Some(Action::Insert(chr)),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Self::new();
+        let mut bind = |code, modifiers, action| {
+            keymap.bind(KeyEvent::new(code, modifiers), action);
+        };
+
+        let none = KeyModifiers::NONE;
+        let ctrl = KeyModifiers::CONTROL;
+        let alt = KeyModifiers::ALT;
+
+        bind(KeyCode::Enter, none, Action::Enter);
+        bind(KeyCode::Tab, none, Action::Complete);
+        bind(KeyCode::BackTab, none, Action::CompleteBackward);
+        bind(KeyCode::Left, none, Action::Left);
+        bind(KeyCode::Right, none, Action::Right);
+        bind(KeyCode::Char('f'), ctrl, Action::Right);
+        bind(KeyCode::Up, none, Action::Up);
+        bind(KeyCode::Down, none, Action::Down);
+        bind(KeyCode::Home, none, Action::LineStart);
+        bind(KeyCode::End, none, Action::LineEnd);
+        bind(KeyCode::Left, ctrl, Action::WordLeft);
+        bind(KeyCode::Char('b'), alt, Action::WordLeft);
+        bind(KeyCode::Right, ctrl, Action::WordRight);
+        bind(KeyCode::Char('f'), alt, Action::WordRight);
+        bind(KeyCode::PageUp, none, Action::HistoryUp);
+        bind(KeyCode::PageDown, none, Action::HistoryDown);
+        bind(KeyCode::Char('r'), ctrl, Action::SearchBackward);
+        bind(KeyCode::Char('s'), ctrl, Action::SearchForward);
+        bind(KeyCode::Backspace, none, Action::Backspace);
+        bind(KeyCode::Delete, none, Action::Delete);
+        bind(KeyCode::Char('w'), ctrl, Action::KillWordBackward);
+        bind(KeyCode::Backspace, alt, Action::KillWordBackward);
+        // Alt-Backspace is sent as an escaped DEL by most terminals
+        bind(KeyCode::Char('\x7f'), alt, Action::KillWordBackward);
+        bind(KeyCode::Char('d'), alt, Action::KillWordForward);
+        bind(KeyCode::Char('u'), ctrl, Action::KillLineBackward);
+        bind(KeyCode::Char('k'), ctrl, Action::KillLineForward);
+        bind(KeyCode::Char('y'), ctrl, Action::Yank);
+        bind(KeyCode::Char('y'), alt, Action::YankPop);
+        bind(KeyCode::Char('z'), ctrl, Action::Undo);
+        bind(KeyCode::Char('_'), ctrl, Action::Undo);
+        // Ctrl-_ is sent as the same byte as Ctrl-7 by most terminals
+        bind(KeyCode::Char('7'), ctrl, Action::Undo);
+        bind(KeyCode::Char('/'), alt, Action::Redo);
+        bind(KeyCode::Char('l'), ctrl, Action::ClearScreen);
+        bind(KeyCode::Char('c'), ctrl, Action::Interrupt);
+        bind(KeyCode::Char('d'), ctrl, Action::Eof);
+
+        keymap
+    }
+}
@@ -1,4 +1,5 @@
-use std::io::{self, prelude::*};
+use std::io::Write;
+use std::ops::Range;
 
 /// `LangInterface` is a trait used by [Repl](crate::Repl) to provide dependent specific features.
 ///
@@ -21,7 +22,10 @@ pub trait LangInterface {
     /// This probably would not be linted properly, since in line 2, the context that the word 'line'
     /// is within a comment is lost. For correct behaviour (subject to language linting rules), all
     /// the lines should be processed, but only `lines[index]` should be written to `stdout`.
-    fn print_line(stdout: &mut io::Stdout, lines: &[String], index: usize) -> crate::Result<()> {
+    ///
+    /// `stdout` is whatever output the [Repl](crate::Repl) was created with, so it should always be
+    /// written to instead of printing directly.
+    fn print_line<W: Write>(stdout: &mut W, lines: &[String], index: usize) -> crate::Result<()> {
         stdout
             .write_all(lines[index].as_bytes())
             .map_err(crossterm::ErrorKind::IoError)
@@ -56,12 +60,79 @@ pub trait LangInterface {
     ///     █ // CURSOR HERE
     /// ```
     fn get_indent(lines: &[String]) -> usize {
-        if let Some(ref line) = lines.last() {
+        if let Some(line) = lines.last() {
             line.len() - line.trim_start().len()
         } else {
             0
         }
     }
+
+    /// Whether the character is part of a word. This is used to move and delete a word at a time,
+    /// for example with `Ctrl-Left` and `Ctrl-W`.
+    ///
+    /// By default words are made of alphanumeric characters and `_`. If paths such as `foo::bar`
+    /// should be treated as a single word, `:` can also be included.
+    fn is_word_char(chr: char) -> bool {
+        chr.is_alphanumeric() || chr == '_'
+    }
+
+    /// Given the lines, this function should give the text to suggest after them, which is shown
+    /// greyed out and can be accepted with Right or End. `from_history` is the rest of the most
+    /// recent history entry which starts with the lines, if there is one. It can contain new lines,
+    /// in which case accepting it adds lines to the command.
+    ///
+    /// This is only used when the cursor is at the end of the lines, and suggestions are turned on
+    /// with [`Repl::set_suggestions`](crate::Repl::set_suggestions). By default `from_history` is
+    /// suggested.
+    fn hint(_lines: &[String], from_history: Option<String>) -> Option<String> {
+        from_history
+    }
+
+    /// Given the lines and the position of the cursor as the index of the line and the character
+    /// in it, this function should give the candidates to complete the text at the cursor. This
+    /// is used when Tab is pressed anywhere other than in the indentation of a line.
+    ///
+    /// If there is only one candidate, it is inserted straight away. Otherwise, the common prefix
+    /// of the candidates is inserted, and a menu is shown below the prompt where Tab and Shift-Tab,
+    /// or Up and Down, go through the candidates. Enter accepts the selected candidate, and Esc
+    /// goes back to what was typed.
+    ///
+    /// For example, with the cursor at the end of `let x = ve`, the candidates could be `vec!`
+    /// and `version`, both replacing the characters `8..10`.
+    ///
+    /// By default there are no candidates.
+    fn complete(_lines: &[String], _lineno: usize, _charno: usize) -> Vec<Completion> {
+        Vec::new()
+    }
+}
+
+/// A candidate given by [`LangInterface::complete`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    /// The text which is inserted
+    pub replacement: String,
+    /// The characters of the line with the cursor which are replaced, usually the start of the
+    /// word up to the cursor. It is counted in characters, not bytes. Candidates with a range which
+    /// is reversed or goes past the end of the line are ignored.
+    pub range: Range<usize>,
+    /// What is shown in the menu, if it should be different from the replacement
+    pub display: Option<String>,
+}
+
+impl Completion {
+    /// Create a candidate which replaces `range` with `replacement`
+    pub fn new<S: Into<String>>(replacement: S, range: Range<usize>) -> Self {
+        Self {
+            replacement: replacement.into(),
+            range,
+            display: None,
+        }
+    }
+
+    /// What is shown for the candidate in the menu
+    pub fn display(&self) -> &str {
+        self.display.as_deref().unwrap_or(&self.replacement)
+    }
 }
 
 pub struct DefaultLangInterface;
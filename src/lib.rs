@@ -4,7 +4,8 @@
 //!
 //! There are special 2 commands handled by the repl:
 //! - `clear` - clears the screen
-//! - `exit`  - exits
+//! - `exit`  - gives back [`Signal::Exit`]
+//!
 //! These can be changed with the [`repl.set_clear_keyword()`](Repl::set_clear_keyword) and
 //! [`repl.set_exit_keyword()`](Repl::set_exit_keyword) respectively. Any other special commands can
 //! be handled within the execution loop.
@@ -36,8 +37,8 @@
 //! type argument.
 //!
 //! ```
-//! use std::io::{self, prelude::*};
-//! use shelp::{Repl, Color, LangInterface, Result};
+//! use std::io::Write;
+//! use shelp::{Repl, Color, LangInterface, Result, Signal};
 //! // You can use any library, but currently only crossterm is used in the library for terminal.
 //! use crossterm::style::Colorize;
 //!
@@ -45,14 +46,14 @@
 //! // We want to override the linting so numbers are coloured, but we don't have a specific way of
 //! // getting the indentation, so we do not override that.
 //! impl LangInterface for MyLangInterface {
-//!     fn print_line(_: &mut io::Stdout, lines: &[String], index: usize) -> Result<()> {
+//!     fn print_line<W: Write>(stdout: &mut W, lines: &[String], index: usize) -> Result<()> {
 //!         // NOTE this is simple linting and has no multi-line context. For more information on
 //!         // the reason all lines are given, see LangInterface::print_line
 //!         for i in lines[index].chars() {
 //!             if i.is_numeric() {
-//!                 print!("{}", i.magenta());
+//!                 write!(stdout, "{}", i.magenta())?;
 //!             } else {
-//!                 print!("{}", i);
+//!                 write!(stdout, "{}", i)?;
 //!             }
 //!         }
 //!         Ok(())
@@ -66,19 +67,40 @@
 //! //     // You can have dynamic colours if you don't use the iterator. It also allows you to use the
 //! //     // errors instead of them being ignored.
 //! //     // NOTE here it is unwrapped, but it should be dealt with in a better way.
-//! //     let command = repl.next(Color::Blue).unwrap();
+//! //     let command = match repl.next(Color::Blue).unwrap() {
+//! //         Signal::Command(command) => command,
+//! //         Signal::Interrupted => continue,
+//! //         _ => break,
+//! //     };
 //! //
 //! //     <Do something>
 //! // }
 //! // NOTE the above is commented out for doc test reasons
 //! ```
+//!
+//! ## Testing
+//!
+//! The [`testing`] module can run a [`Repl`] against a simulated screen, so that the rendering of
+//! a [`LangInterface`] can be checked without a terminal.
 
 #[macro_use]
 mod macros;
+pub(crate) mod input;
+pub(crate) mod keymap;
 pub(crate) mod lang;
 mod repl;
+pub(crate) mod script;
+pub mod testing;
 
 pub use crossterm::{style::Color, Result};
-pub use lang::LangInterface;
+pub use input::{InputSource, LineInput, TerminalInput};
+pub use keymap::{Action, Callback, Keymap};
+pub use lang::{Completion, LangInterface};
+pub use repl::editor::Editor;
+pub use repl::history::{History, HistoryEntry, Metadata};
 pub use repl::iter::ReplIter;
-pub use repl::Repl;
+pub use repl::store::{FileStore, HistoryStore};
+pub use repl::{
+    EditMode, HistoryControl, HistoryExpansion, HistoryNavigation, InterruptPolicy, Repl, Signal,
+};
+pub use script::Commands;
@@ -1,16 +1,12 @@
 #[doc(hidden)]
 #[macro_export]
 macro_rules! history_up {
-    ($self:ident, $stdout:ident, $c:ident, $lines:ident, $colour:ident) => {{
+    ($self:ident, $c:ident, $lines:ident) => {{
         $c.use_history = true;
 
-        let lines = match $self.history.prev() {
+        let lines = match $self.history_prev(&$lines) {
             Some(s) => {
-                $self.print_lines(&mut $stdout, &mut $c, &s, $colour)?;
                 $c.lineno = s.len() - 1;
-                if $c.lineno > 0 {
-                    queue!($stdout, cursor::MoveDown($c.lineno as u16))?;
-                }
                 s
             }
             None => match $self.history.cur() {
@@ -29,26 +25,20 @@ macro_rules! history_up {
         }
     }};
 
-    (retain $self:ident, $stdout:ident, $c:ident, $lines:ident, $colour:ident) => {{
+    (retain $self:ident, $c:ident, $lines:ident) => {{
         let lineno = $c.lineno;
-        if lineno > 0 {
-            queue!($stdout, cursor::MoveUp(lineno as u16))?;
-            $c.lineno = 0;
-        }
+        $c.lineno = 0;
 
-        history_up!($self, $stdout, $c, $lines, $colour);
-        if lineno < $c.lineno {
-            queue!($stdout, cursor::MoveUp(($c.lineno - lineno) as u16))?;
-            $c.lineno = lineno;
-        };
+        history_up!($self, $c, $lines);
+        $c.lineno = min(lineno, $self.cur(&$c, &$lines).len() - 1);
     }};
 }
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! history_down {
-    ($self:ident, $stdout:ident, $c:ident, $lines:ident, $colour:ident) => {{
-        let lines = match $self.history.next() {
+    ($self:ident, $c:ident, $lines:ident) => {{
+        let lines = match $self.history_next(&$lines) {
             Some(s) => s,
             None => {
                 $c.use_history = false;
@@ -56,11 +46,7 @@ macro_rules! history_down {
             }
         };
 
-        if $c.lineno > 0 {
-            queue!($stdout, cursor::MoveUp($c.lineno as u16))?;
-        }
         $c.lineno = 0;
-        $self.print_lines(&mut $stdout, &mut $c, lines, $colour)?;
 
         let s_len = lines[$c.lineno].chars().count();
 
@@ -69,13 +55,9 @@ macro_rules! history_down {
         }
     }};
 
-    (retain $self:ident, $stdout:ident, $c:ident, $lines:ident, $colour:ident) => {{
+    (retain $self:ident, $c:ident, $lines:ident) => {{
         let lineno = $c.lineno;
-        history_down!($self, $stdout, $c, $lines, $colour);
-        let lineno = min(lineno, $self.cur(&$c, &$lines).len() - 1);
-        if lineno > 0 {
-            queue!($stdout, cursor::MoveDown(lineno as u16))?;
-            $c.lineno = lineno;
-        };
+        history_down!($self, $c, $lines);
+        $c.lineno = min(lineno, $self.cur(&$c, &$lines).len() - 1);
     }};
 }
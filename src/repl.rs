@@ -1,14 +1,34 @@
-mod history;
+mod completion;
+pub(crate) mod editor;
+mod expansion;
+pub(crate) mod history;
 pub(crate) mod iter;
-
-use history::History;
-
+mod kill_ring;
+mod search;
+pub(crate) mod store;
+mod undo;
+mod vi;
+
+use completion::Menu;
+use editor::Editor;
+use history::{History, HistoryEntry};
+use kill_ring::{Direction, KillRing};
+use search::Search;
+use store::HistoryStore;
+use undo::{Snapshot, UndoStack};
+use vi::Vi;
+
+use crate::input::{InputSource, TerminalInput};
+use crate::keymap::{Action, Keymap};
 use crate::lang::{DefaultLangInterface, LangInterface};
+use crate::script::read_block;
 use crossterm::{cursor, event, execute, queue, style, terminal};
+use std::cell::RefCell;
 use std::cmp::min;
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
 use std::marker::PhantomData;
 use std::path::PathBuf;
+use std::time::Duration;
 
 /// `Repl` interacts with the terminal to provide easy interactive shells.
 ///
@@ -35,13 +55,63 @@ use std::path::PathBuf;
 ///   The maximum amount of commands stored in the history. Default capacity is 64. If there are
 ///   already 64 commands in the history, the oldest one will be forgotten.
 /// - `exit_keyword`
-///   The keyword to exit the repl, it exits the process, so should not be used if any cleanup is a
-///   required before closing repl. See [`set_exit_keyword`](Repl::set_exit_keyword)
+///   The keyword to exit the repl, when entered [`Signal::Exit`] is returned instead of a command.
+///   See [`set_exit_keyword`](Repl::set_exit_keyword)
 /// - `clear_keyword`
 ///   Clears the screen. See [`set_clear_keyword`](Repl::set_exit_keyword)
-pub struct Repl<L: LangInterface = DefaultLangInterface> {
+/// - `interrupt_policy`
+///   What to do when Ctrl-C is pressed. By default the current input is discarded. See
+///   [`set_interrupt_policy`](Repl::set_interrupt_policy)
+/// - `keymap`
+///   What is done when each key is pressed. See [`Keymap`] for the default bindings, and
+///   [`with_keymap`](Repl::with_keymap) or [`set_keymap`](Repl::set_keymap) to change them
+/// - `suggestions`
+///   Whether the rest of a history entry which starts with the input is suggested after it. It is
+///   disabled by default. See [`set_suggestions`](Repl::set_suggestions)
+/// - `history_navigation`
+///   Which history entries are gone through with Up and Down. By default it is all of them. See
+///   [`set_history_navigation`](Repl::set_history_navigation)
+/// - `skip_failed`
+///   Whether commands which were reported to have failed with
+///   [`report_status`](Repl::report_status) are skipped when going through history. It is
+///   disabled by default. See [`set_skip_failed`](Repl::set_skip_failed)
+/// - `incremental_history`
+///   Whether each command is saved to the history file as soon as it is run, instead of when the
+///   repl exits. It is disabled by default. See
+///   [`set_incremental_history`](Repl::set_incremental_history)
+/// - `history_control`
+///   Which commands are left out of history, such as duplicates or ones which start with a space.
+///   By default every command is kept. See [`set_history_control`](Repl::set_history_control)
+///   and [`set_history_filter`](Repl::set_history_filter)
+/// - `history_expansion`
+///   Whether references to history like `!!` and `^old^new` are expanded in commands. It is
+///   disabled by default. See [`set_history_expansion`](Repl::set_history_expansion)
+/// - `edit_mode`
+///   Whether emacs style or vi style keys are used to edit commands. Emacs style is the default.
+///   See [`set_edit_mode`](Repl::set_edit_mode)
+/// - `input`
+///   Where the key events are read from. By default they are read from the terminal, but any
+///   [`InputSource`] can be given with [`with_input`](Repl::with_input). If the input is not
+///   interactive, such as when `stdin` is piped, whole lines are read instead, and a command is
+///   given once [`LangInterface::get_indent`] says it is complete.
+/// - `output`
+///   Where the repl is rendered. By default it is written to `stdout`, but anything implementing
+///   [`Write`] can be given with [`with_output`](Repl::with_output), for example `stderr`.
+pub struct Repl<
+    L: LangInterface = DefaultLangInterface,
+    I: InputSource = TerminalInput,
+    W: Write = io::Stdout,
+> {
     /// The history of commands run.
     history: History,
+    /// The error given when the history file was read, if it could not be
+    history_error: Option<io::Error>,
+    /// Whether each command is saved to the history file once it is run (default is false)
+    incremental_history: bool,
+    /// Whether references to history are expanded (default is [`HistoryExpansion::Off`])
+    history_expansion: HistoryExpansion,
+    /// Text which has been killed, kept across commands so that it can be yanked back later.
+    kill_ring: KillRing,
     /// What to print as the prompt:
     ///
     /// > <some-code>
@@ -63,6 +133,30 @@ pub struct Repl<L: LangInterface = DefaultLangInterface> {
     exit_keyword: &'static str,
     /// The keyword which corresponds to the clear command (default is 'clear')
     clear_keyword: &'static str,
+    /// What to do when Ctrl-C is pressed (default is [`InterruptPolicy::Cancel`])
+    interrupt_policy: InterruptPolicy,
+    /// The number of times Ctrl-C has been pressed in a row on an empty prompt
+    interrupts: usize,
+    /// The actions run when keys are pressed (default is [`Keymap::default`])
+    keymap: Keymap,
+    /// Whether text from history is suggested after the input (default is false)
+    suggestions: bool,
+    /// Which history entries Up and Down go through (default is [`HistoryNavigation::All`])
+    history_navigation: HistoryNavigation,
+    /// The state of the vi bindings, if they are used instead of the default emacs style ones.
+    ///
+    /// It is wrapped in `RefCell` for interior mutability, so that it can be changed while the
+    /// lines are edited using only a shared reference.
+    vi: RefCell<Option<Vi>>,
+    /// What to print before the leader in vi insert, normal and visual modes
+    mode_indicators: [&'static str; 3],
+    /// The source of events to respond to
+    input: I,
+    /// Where the repl is rendered.
+    ///
+    /// It is wrapped in `RefCell` for interior mutability, so that it can be written to using only
+    /// a shared reference.
+    output: RefCell<W>,
     _lang_interface: PhantomData<L>,
 }
 
@@ -83,24 +177,14 @@ impl Repl<DefaultLangInterface> {
         capacity: usize,
         path: Option<PathBuf>,
     ) -> Self {
-        let should_persist = path.is_some();
-
-        let mut repl = Self {
-            history: History::with_capacity(capacity, path),
+        Self::with_io(
             leader,
-            leader_len: leader.chars().count(),
             continued_leader,
-            continued_leader_len: leader.chars().count(),
-            exit_keyword: "exit",
-            clear_keyword: "clear",
-            _lang_interface: PhantomData,
-        };
-
-        if should_persist {
-            let _ = repl.history.read_from_file();
-        }
-
-        repl
+            capacity,
+            path,
+            TerminalInput,
+            io::stdout(),
+        )
     }
 }
 
@@ -120,27 +204,121 @@ impl<L: LangInterface> Repl<L> {
         continued_leader: &'static str,
         capacity: usize,
         path: Option<PathBuf>,
+    ) -> Self {
+        Self::with_io(
+            leader,
+            continued_leader,
+            capacity,
+            path,
+            TerminalInput,
+            io::stdout(),
+        )
+    }
+}
+
+impl<L: LangInterface, I: InputSource> Repl<L, I> {
+    /// Create a `Repl` with specified language interface, history capacity and input source.
+    pub fn with_input(
+        leader: &'static str,
+        continued_leader: &'static str,
+        capacity: usize,
+        path: Option<PathBuf>,
+        input: I,
+    ) -> Self {
+        Self::with_io(
+            leader,
+            continued_leader,
+            capacity,
+            path,
+            input,
+            io::stdout(),
+        )
+    }
+}
+
+impl<L: LangInterface, W: Write> Repl<L, TerminalInput, W> {
+    /// Create a `Repl` with specified language interface, history capacity and output.
+    pub fn with_output(
+        leader: &'static str,
+        continued_leader: &'static str,
+        capacity: usize,
+        path: Option<PathBuf>,
+        output: W,
+    ) -> Self {
+        Self::with_io(
+            leader,
+            continued_leader,
+            capacity,
+            path,
+            TerminalInput,
+            output,
+        )
+    }
+}
+
+impl<L: LangInterface, I: InputSource, W: Write> Repl<L, I, W> {
+    /// Create a `Repl` with specified language interface, history capacity, input source and
+    /// output.
+    pub fn with_io(
+        leader: &'static str,
+        continued_leader: &'static str,
+        capacity: usize,
+        path: Option<PathBuf>,
+        input: I,
+        output: W,
     ) -> Self {
         let should_persist = path.is_some();
 
         let mut repl = Self {
             history: History::with_capacity(capacity, path),
+            history_error: None,
+            incremental_history: false,
+            history_expansion: HistoryExpansion::Off,
+            kill_ring: KillRing::with_capacity(10),
             leader,
             leader_len: leader.chars().count(),
             continued_leader,
-            continued_leader_len: leader.chars().count(),
+            continued_leader_len: continued_leader.chars().count(),
             exit_keyword: "exit",
             clear_keyword: "clear",
+            interrupt_policy: InterruptPolicy::Cancel,
+            interrupts: 0,
+            keymap: Keymap::default(),
+            suggestions: false,
+            history_navigation: HistoryNavigation::All,
+            vi: RefCell::new(None),
+            mode_indicators: ["(ins) ", "(cmd) ", "(vis) "],
+            input,
+            output: RefCell::new(output),
             _lang_interface: PhantomData,
         };
 
         if should_persist {
-            let _ = repl.history.read_from_file();
+            repl.history_error = repl
+                .history
+                .load()
+                .err()
+                .filter(|e| e.kind() != io::ErrorKind::NotFound);
         }
 
         repl
     }
 
+    /// The error given when the history file was read, if it exists but could not be read. In that
+    /// case the file is left as it is instead of being overwritten when the repl exits.
+    pub fn history_error(&self) -> Option<&io::Error> {
+        self.history_error.as_ref()
+    }
+
+    /// Sets where history is persisted instead of the history file, and reads the commands in it.
+    /// Any error reading them is given by [`history_error`](Repl::history_error).
+    ///
+    /// Commands read from the history file are replaced by the ones in the store, but commands run
+    /// since then which were not saved are kept, and are saved to the new store.
+    pub fn set_history_store<S: HistoryStore + 'static>(&mut self, store: S) {
+        self.history_error = self.history.set_store(Box::new(store)).err();
+    }
+
     /// Sets the exit keyword. If you don't want any exit keyword, set it to an empty string
     pub fn set_exit_keyword(&mut self, exit_keyword: &'static str) {
         self.exit_keyword = exit_keyword
@@ -151,6 +329,147 @@ impl<L: LangInterface> Repl<L> {
         self.clear_keyword = clear_keyword
     }
 
+    /// Sets what should be done when Ctrl-C is pressed
+    pub fn set_interrupt_policy(&mut self, interrupt_policy: InterruptPolicy) {
+        self.interrupt_policy = interrupt_policy;
+        self.interrupts = 0;
+    }
+
+    /// Sets the keys which undo and redo edits of the current command, replacing the keys which
+    /// were bound to [`Action::Undo`] and [`Action::Redo`] in the keymap
+    pub fn set_undo_keys(
+        &mut self,
+        undo_keys: Vec<event::KeyEvent>,
+        redo_keys: Vec<event::KeyEvent>,
+    ) {
+        self.keymap
+            .unbind_where(|action| matches!(action, Action::Undo | Action::Redo));
+
+        for key in undo_keys {
+            self.keymap.bind(key, Action::Undo);
+        }
+        for key in redo_keys {
+            self.keymap.bind(key, Action::Redo);
+        }
+    }
+
+    /// Gives the `Repl` with what is done when each key is pressed set, so that a keymap can be
+    /// installed when it is created:
+    ///
+    /// ```no_run
+    /// # use shelp::{Keymap, Repl};
+    /// let repl = Repl::newd("> ", ". ", None).with_keymap(Keymap::default());
+    /// ```
+    pub fn with_keymap(mut self, keymap: Keymap) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    /// Sets what is done when each key is pressed
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    /// Gives the keymap, so that keys can be bound or unbound
+    pub fn keymap_mut(&mut self) -> &mut Keymap {
+        &mut self.keymap
+    }
+
+    /// Sets whether the rest of the most recent history entry which starts with the input is
+    /// suggested after it, greyed out. The suggestion is accepted with Right or End at the end of
+    /// the input. See [`LangInterface::hint`] to change what is suggested
+    pub fn set_suggestions(&mut self, suggestions: bool) {
+        self.suggestions = suggestions;
+    }
+
+    /// Sets which history entries are gone through with Up and Down
+    pub fn set_history_navigation(&mut self, history_navigation: HistoryNavigation) {
+        self.history_navigation = history_navigation;
+    }
+
+    /// Sets whether commands which failed are skipped when going through history
+    pub fn set_skip_failed(&mut self, skip_failed: bool) {
+        self.history.set_skip_failed(skip_failed);
+    }
+
+    /// Records whether the last command given back from [`next`](Repl::next) succeeded, and how
+    /// long it took to run. If the duration is not given, the time since the command was given back
+    /// is used. It is saved in the history file along with the command.
+    ///
+    /// Nothing is recorded if the command was left out of history, for example by the
+    /// [`HistoryControl`] or the history filter, or because the input is not interactive.
+    pub fn report_status(&mut self, success: bool, duration: Option<Duration>) {
+        self.history.report_status(success, duration);
+        if self.incremental_history {
+            let _ = self.history.write();
+        }
+    }
+
+    /// Sets which commands are left out of history. It is also applied to the commands which have
+    /// already been read from the history file.
+    pub fn set_history_control(&mut self, history_control: HistoryControl) {
+        self.history.set_control(history_control);
+    }
+
+    /// Sets a filter which decides whether a command is kept in history, for example to leave out
+    /// commands containing passwords. Like [`set_history_control`](Repl::set_history_control), it
+    /// is also applied to the commands which have already been read.
+    ///
+    /// ```
+    /// use shelp::Repl;
+    ///
+    /// let mut repl = Repl::newd("> ", ". ", None);
+    /// repl.set_history_filter(|command| !command.contains("password"));
+    /// ```
+    pub fn set_history_filter<F: Fn(&str) -> bool + 'static>(&mut self, filter: F) {
+        self.history.set_filter(Box::new(filter));
+    }
+
+    /// Sets whether references to history like `!!` are expanded in commands before they are given
+    /// back. See [`HistoryExpansion`] for what is expanded.
+    pub fn set_history_expansion(&mut self, history_expansion: HistoryExpansion) {
+        self.history_expansion = history_expansion;
+    }
+
+    /// Sets whether each command is saved to the history file as soon as it is given back from
+    /// [`next`](Repl::next), so that it is not lost if the program crashes. Otherwise the history
+    /// is saved when the repl is dropped.
+    ///
+    /// Either way, only the commands from this repl are added to the file, so that commands saved
+    /// by other sessions sharing it are kept.
+    pub fn set_incremental_history(&mut self, incremental_history: bool) {
+        self.incremental_history = incremental_history;
+    }
+
+    /// The history of commands run, to list or search them
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// The history of commands run, to remove or add commands, or to save or reload it
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    /// Sets whether emacs style or vi style keys are used to edit commands
+    pub fn set_edit_mode(&mut self, edit_mode: EditMode) {
+        *self.vi.get_mut() = match edit_mode {
+            EditMode::Emacs => None,
+            EditMode::Vi => Some(Vi::default()),
+        };
+    }
+
+    /// Sets what is printed before the leader in vi insert, normal and visual modes, to show which
+    /// mode the editor is in. These are only shown when using [`EditMode::Vi`]
+    pub fn set_mode_indicators(
+        &mut self,
+        insert: &'static str,
+        normal: &'static str,
+        visual: &'static str,
+    ) {
+        self.mode_indicators = [insert, normal, visual];
+    }
+
     /// Gives current command based on the cursor
     fn cur<'a>(&'a self, c: &Cursor, lines: &'a [String]) -> &'a [String] {
         if c.use_history {
@@ -167,6 +486,24 @@ impl<L: LangInterface> Repl<L> {
         &self.cur(c, lines)[c.lineno]
     }
 
+    /// Go to the previous history entry, skipping ones which do not start with the typed lines if
+    /// [`HistoryNavigation::Prefix`] is used
+    fn history_prev(&self, lines: &[String]) -> Option<&Vec<String>> {
+        match self.history_navigation {
+            HistoryNavigation::All => self.history.prev(),
+            HistoryNavigation::Prefix => self.history.prev_matching(&lines.join("\n")),
+        }
+    }
+
+    /// Go to the next history entry, skipping ones which do not start with the typed lines if
+    /// [`HistoryNavigation::Prefix`] is used
+    fn history_next(&self, lines: &[String]) -> Option<&Vec<String>> {
+        match self.history_navigation {
+            HistoryNavigation::All => self.history.next(),
+            HistoryNavigation::Prefix => self.history.next_matching(&lines.join("\n")),
+        }
+    }
+
     /// Copy the lines from history into the lines buffer
     fn replace_with_history(&self, lines: &mut Vec<String>) {
         let cur = self.history.cur().unwrap();
@@ -180,333 +517,952 @@ impl<L: LangInterface> Repl<L> {
         self.history.reset_iter();
     }
 
-    fn pre_exit(&self) {
-        let _ = terminal::disable_raw_mode();
-        println!();
-        let _ = self.history.write_to_file();
+    /// Prepares the lines to be edited, saving them first so that the edit can be undone. If a
+    /// history entry is being shown, it is copied into the lines.
+    fn edit(&self, c: &mut Cursor, lines: &mut Vec<String>, undo: &mut UndoStack) {
+        if c.use_history {
+            // The lines which are replaced are saved as well, so that they can be gone back to by
+            // undoing past the edit of the history entry
+            let lineno = lines.len() - 1;
+            undo.push(Snapshot::new(lines, lineno, lines[lineno].chars().count()));
+
+            self.replace_with_history(lines);
+            c.use_history = false;
+        }
+
+        undo.push(Snapshot::new(lines, c.lineno, c.charno));
     }
 
-    fn exit(&self) -> ! {
-        self.pre_exit();
-        std::process::exit(0)
+    /// The text to suggest after the input, from the most recent history entry which starts with
+    /// it and [`LangInterface::hint`]. There is only a suggestion when the cursor is at the end of
+    /// the input.
+    fn hint(&self, c: &Cursor, lines: &[String]) -> Option<String> {
+        if c.use_history
+            || c.lineno + 1 != lines.len()
+            || c.charno != lines[c.lineno].chars().count()
+        {
+            return None;
+        }
+
+        let from_history = if lines.iter().all(|line| line.trim().is_empty()) {
+            None
+        } else {
+            let input = lines.join("\n");
+            self.history
+                .iter()
+                .map(HistoryEntry::command)
+                .find(|entry| entry.len() > input.len() && entry.starts_with(&input))
+                .map(|entry| entry[input.len()..].to_owned())
+        };
+
+        L::hint(lines, from_history).filter(|hint| !hint.is_empty())
+    }
+
+    /// Handles a key while searching through history. Typed characters are added to the query,
+    /// and Ctrl-R and Ctrl-S go to the next match. Esc goes back to the lines from before the
+    /// search, and anything else finishes the search with the match in the lines, giving back the
+    /// action which should then be run.
+    fn search_key(
+        &self,
+        e: event::KeyEvent,
+        search: &mut Option<Search>,
+        c: &mut Cursor,
+        lines: &mut Vec<String>,
+        undo: &mut UndoStack,
+    ) -> Option<Action> {
+        // unwrap because this is only called while searching
+        let s = search.as_mut().unwrap();
+
+        if e.code == event::KeyCode::Esc {
+            let (lineno, charno) = s.original_cursor();
+            c.lineno = lineno;
+            c.charno = charno;
+            *search = None;
+            return None;
+        }
+
+        let action = match self.keymap.action(e) {
+            Some(Action::Insert(chr)) => {
+                s.push(chr, &self.history);
+                None
+            }
+            Some(Action::Backspace) => {
+                s.pop(&self.history);
+                None
+            }
+            Some(Action::SearchBackward) => {
+                s.next(false, &self.history);
+                None
+            }
+            Some(Action::SearchForward) => {
+                s.next(true, &self.history);
+                None
+            }
+            action => {
+                if let Some(i) = s.found() {
+                    self.edit(c, lines, undo);
+                    *lines = self.history[i].clone();
+                }
+                let (lineno, charno) = s.cursor();
+                c.lineno = lineno;
+                c.charno = charno;
+                *search = None;
+
+                // Enter only accepts the match, so that it can be edited before it is run
+                return match action {
+                    Some(Action::Enter) => None,
+                    action => action,
+                };
+            }
+        };
+
+        let (lineno, charno) = s.cursor();
+        c.lineno = lineno;
+        c.charno = charno;
+        action
     }
 
-    /// Print a command
+    /// Completes the text at the cursor with the candidates from [`LangInterface::complete`]. If
+    /// there is only one, it is inserted, otherwise their common prefix is inserted and a menu to
+    /// choose between them is given.
+    fn complete(
+        &self,
+        c: &mut Cursor,
+        lines: &mut Vec<String>,
+        undo: &mut UndoStack,
+    ) -> Option<Menu> {
+        let line = self.cur_str(c, lines).to_owned();
+        // Candidates with a range which cannot be used are left out, rather than panicking
+        let candidates: Vec<_> = L::complete(self.cur(c, lines), c.lineno, c.charno)
+            .into_iter()
+            .filter(|candidate| completion::is_valid(&line, candidate))
+            .collect();
+
+        let completion = match candidates.len() {
+            0 => return None,
+            1 => Some(candidates[0].clone()),
+            _ => completion::common_prefix(&line, &candidates),
+        };
+
+        if let Some(completion) = completion {
+            let (completed, charno) = completion::complete(&line, &completion);
+            if completed != line {
+                self.edit(c, lines, undo);
+                lines[c.lineno] = completed;
+            }
+            c.charno = charno;
+        }
+
+        if candidates.len() > 1 {
+            let typed = (self.cur_str(c, lines).to_owned(), c.charno);
+            Some(Menu::new(candidates, line, typed))
+        } else {
+            None
+        }
+    }
+
+    /// Go back to the lines before the last edit
+    fn undo_edit(&self, c: &mut Cursor, lines: &mut Vec<String>, undo: &mut UndoStack) {
+        let current = Snapshot::new(self.cur(c, lines), c.lineno, c.charno);
+        if let Some(snapshot) = undo.undo(current) {
+            self.restore(c, lines, snapshot);
+        }
+    }
+
+    /// Go back to the lines before the last undo
+    fn redo_edit(&self, c: &mut Cursor, lines: &mut Vec<String>, undo: &mut UndoStack) {
+        let current = Snapshot::new(self.cur(c, lines), c.lineno, c.charno);
+        if let Some(snapshot) = undo.redo(current) {
+            self.restore(c, lines, snapshot);
+        }
+    }
+
+    /// Replace the lines with ones saved by undo or redo
+    fn restore(&self, c: &mut Cursor, lines: &mut Vec<String>, snapshot: Snapshot) {
+        if c.use_history {
+            self.history.reset_iter();
+            c.use_history = false;
+        }
+
+        *lines = snapshot.lines;
+        c.lineno = snapshot.lineno;
+        c.charno = snapshot.charno;
+    }
+
+    /// Shows the changes made by a [`Callback`](crate::Callback) to the lines and cursor, and prints
+    /// anything it printed above the block
+    fn apply(
+        &self,
+        stdout: &mut W,
+        c: &mut Cursor,
+        lines: &mut Vec<String>,
+        undo: &mut UndoStack,
+        mut editor: Editor,
+    ) -> crate::Result<()> {
+        let printed = editor.take_printed();
+        if !printed.is_empty() {
+            if c.row > 0 {
+                queue!(stdout, cursor::MoveUp(c.row as u16))?;
+            }
+            queue!(
+                stdout,
+                cursor::MoveToColumn(0),
+                terminal::Clear(terminal::ClearType::FromCursorDown),
+            )?;
+
+            for line in printed.iter().flat_map(|text| text.split('\n')) {
+                // '\n' only moves down in raw mode, and scrolls if it is the last row
+                queue!(stdout, style::Print(line), style::Print("\r\n"))?;
+            }
+            c.row = 0;
+        }
+
+        let (lineno, charno) = editor.cursor();
+        if editor.lines() != self.cur(c, lines) {
+            self.edit(c, lines, undo);
+            *lines = editor.into_lines();
+        }
+        c.lineno = lineno;
+        c.charno = charno;
+
+        Ok(())
+    }
+
+    fn pre_exit(&mut self) {
+        if self.input.is_interactive() {
+            let _ = self.input.disable_raw_mode();
+            let _ = self.output.borrow_mut().write_all(b"\r\n");
+        }
+        // There is nowhere else to give the error, so it is printed like bash does
+        if self.history.store().is_some() {
+            if let Err(e) = self.history.write() {
+                let _ = writeln!(self.output.borrow_mut(), "Could not save history: {}", e);
+            }
+        }
+    }
+
+    /// What to print before the leader to show the vi mode
+    fn mode_indicator(&self, vi: Option<&Vi>) -> &'static str {
+        match vi.map(|vi| vi.mode) {
+            None => "",
+            Some(vi::Mode::Insert) => self.mode_indicators[0],
+            Some(vi::Mode::Normal) => self.mode_indicators[1],
+            Some(vi::Mode::Visual { .. }) => self.mode_indicators[2],
+        }
+    }
+
+    /// Print a command, replacing the block which was last printed. The terminal cursor is expected
+    /// to be on line `c.row` of that block, and is left on line `c.lineno` of the new one.
     fn print_lines(
         &self,
-        stdout: &mut std::io::Stdout,
+        stdout: &mut W,
         c: &mut Cursor,
         lines: &[String],
         colour: style::Color,
+        vi: Option<&Vi>,
+        decoration: Decoration,
     ) -> crate::Result<()> {
-        if c.lineno > 0 {
-            queue!(stdout, cursor::MoveUp(c.lineno as u16))?;
+        let indicator = self.mode_indicator(vi);
+        // While searching, the prompt is printed instead of the leader and the match is selected
+        let (prompt, selection) = match &decoration {
+            Decoration::Search(search) => (Some(search.prompt()), search.selection()),
+            _ => (None, vi.and_then(|vi| vi.selection(c, lines))),
+        };
+        let (first_leader, first_leader_len) = match &prompt {
+            Some(prompt) => (prompt.as_str(), prompt.chars().count()),
+            None => (self.leader, self.leader_len),
+        };
+
+        if c.row > 0 {
+            queue!(stdout, cursor::MoveUp(c.row as u16))?;
         }
 
         queue!(
             stdout,
-            terminal::Clear(terminal::ClearType::CurrentLine),
+            cursor::MoveToColumn(0),
             terminal::Clear(terminal::ClearType::FromCursorDown),
         )?;
-        let mut is_first = true;
+
+        let indicator_len = indicator.chars().count();
 
         for index in 0..lines.len() {
-            let leader = if is_first {
-                is_first = false;
-                self.leader
+            let (leader, leader_len) = if index == 0 {
+                queue!(
+                    stdout,
+                    style::SetForegroundColor(colour),
+                    style::Print(indicator),
+                )?;
+                (first_leader, indicator_len + first_leader_len)
             } else {
-                self.continued_leader
+                // '\n' only moves down in raw mode, and scrolls if it is the last row
+                queue!(stdout, style::Print("\r\n"))?;
+                (self.continued_leader, self.continued_leader_len)
             };
 
             queue!(
                 stdout,
-                cursor::MoveToColumn(0),
                 style::SetForegroundColor(colour),
                 style::Print(leader),
+                style::ResetColor,
             )?;
             L::print_line(stdout, lines, index)?;
-            queue!(stdout, style::Print("\n"))?;
+
+            // The selection is printed again over the line, in reverse video
+            if let Some((start, end, linewise)) = selection {
+                if (start.0..=end.0).contains(&index) {
+                    let line = &lines[index];
+                    let from = if linewise || index > start.0 {
+                        0
+                    } else {
+                        start.1
+                    };
+                    let to = if linewise || index < end.0 {
+                        line.chars().count()
+                    } else {
+                        end.1 + 1
+                    };
+                    let selected: String = line.chars().take(to).skip(from).collect();
+
+                    queue!(
+                        stdout,
+                        cursor::MoveToColumn((leader_len + from + 1) as u16),
+                        style::SetAttribute(style::Attribute::Reverse),
+                        style::Print(selected),
+                        style::SetAttribute(style::Attribute::Reset),
+                    )?;
+                }
+            }
         }
 
+        c.below = 0;
+        c.decorated = !matches!(decoration, Decoration::None);
+        match decoration {
+            Decoration::None | Decoration::Search(_) => {}
+            Decoration::Hint(hint) => {
+                queue!(stdout, style::SetForegroundColor(style::Color::DarkGrey))?;
+                for (i, part) in hint.split('\n').enumerate() {
+                    if i > 0 {
+                        queue!(
+                            stdout,
+                            style::Print("\r\n"),
+                            style::Print(self.continued_leader)
+                        )?;
+                        c.below += 1;
+                    }
+                    queue!(stdout, style::Print(part))?;
+                }
+                queue!(stdout, style::ResetColor)?;
+            }
+            Decoration::Menu(menu) => {
+                let padding = " ".repeat(self.continued_leader_len);
+                for (candidate, selected) in menu.visible() {
+                    queue!(stdout, style::Print("\r\n"), style::Print(&padding))?;
+                    if selected {
+                        queue!(
+                            stdout,
+                            style::SetAttribute(style::Attribute::Reverse),
+                            style::Print(candidate),
+                            style::SetAttribute(style::Attribute::Reset),
+                        )?;
+                    } else {
+                        queue!(stdout, style::Print(candidate))?;
+                    }
+                    c.below += 1;
+                }
+            }
+        }
+
+        c.lineno = min(c.lineno, lines.len() - 1);
+        let len = lines[c.lineno].chars().count();
+        // Outside of insert mode, the cursor is on a character rather than between them
+        let max = match vi.map(|vi| vi.mode) {
+            Some(vi::Mode::Insert) | None => len,
+            Some(_) => len.saturating_sub(1),
+        };
+        c.charno = min(c.charno, max);
+        c.row = c.lineno;
+
         let leader_len = if c.lineno == 0 {
-            self.leader_len
+            indicator_len + first_leader_len
         } else {
             self.continued_leader_len
         };
 
-        c.charno = min(c.charno, lines[c.lineno].chars().count());
+        let up = lines.len() - 1 - c.lineno + c.below;
+        if up > 0 {
+            queue!(stdout, cursor::MoveUp(up as u16))?;
+        }
 
         execute!(
             stdout,
-            cursor::MoveUp((lines.len() - c.lineno) as u16),
-            cursor::MoveToColumn((leader_len + c.charno) as u16)
+            cursor::MoveToColumn((leader_len + c.charno + 1) as u16)
         )
     }
 
-    /// The main function, gives the next command
-    pub fn next(&mut self, colour: style::Color) -> crate::Result<String> {
-        let mut stdout = std::io::stdout();
-        let mut lines = Vec::new();
-        lines.push(String::new());
+    /// The main function, gives the next command, or why no command could be given
+    pub fn next(&mut self, colour: style::Color) -> crate::Result<Signal> {
+        self.history.reset_last_pushed();
+        if !self.input.is_interactive() {
+            return self.next_line();
+        }
 
-        let mut c = Cursor::default();
+        let mut lines = vec![String::new()];
+        loop {
+            let mut command = match self.read_command(colour, lines)? {
+                Signal::Command(command) => command,
+                signal => return Ok(signal),
+            };
 
-        terminal::enable_raw_mode()?;
+            if self.history_expansion != HistoryExpansion::Off {
+                match expansion::expand(&command, &self.history) {
+                    // The expanded command is edited again, instead of being given back
+                    Ok(Some(expanded)) if self.history_expansion == HistoryExpansion::Verify => {
+                        lines = expanded.split('\n').map(str::to_owned).collect();
+                        continue;
+                    }
+                    // Like bash, the expanded command is shown before it is given back
+                    Ok(Some(expanded)) => {
+                        let mut stdout = self.output.borrow_mut();
+                        stdout.write_all(expanded.replace('\n', "\r\n").as_bytes())?;
+                        stdout.write_all(b"\r\n")?;
+                        command = expanded;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        let mut stdout = self.output.borrow_mut();
+                        stdout.write_all(e.as_bytes())?;
+                        stdout.write_all(b"\r\n")?;
+                        lines = command.split('\n').map(str::to_owned).collect();
+                        continue;
+                    }
+                }
+            }
 
-        execute!(
-            stdout,
-            style::SetForegroundColor(colour),
-            style::Print(self.leader),
-            style::ResetColor
+            self.history
+                .push_lines(command.split('\n').map(str::to_owned).collect());
+            if self.incremental_history {
+                let _ = self.history.write();
+            }
+
+            return Ok(Signal::Command(command));
+        }
+    }
+
+    /// Reads a command, starting with the given lines with the cursor at the end of them
+    fn read_command(
+        &mut self,
+        colour: style::Color,
+        mut lines: Vec<String>,
+    ) -> crate::Result<Signal> {
+        let mut stdout = self.output.borrow_mut();
+        let mut vi = self.vi.borrow_mut();
+        if let Some(vi) = vi.as_mut() {
+            vi.reset();
+        }
+
+        let mut c = Cursor::default();
+        c.lineno = lines.len() - 1;
+        c.charno = lines[c.lineno].chars().count();
+        let mut undo = UndoStack::default();
+        // Whether the last key killed text, the range of characters the last key yanked, and
+        // whether the last key inserted a character
+        let mut killed = false;
+        let mut yanked = None;
+        let mut inserted = false;
+        // The candidates to complete the text at the cursor, if there was more than one, and the
+        // text suggested after the input
+        let mut menu: Option<Menu> = None;
+        let mut hint: Option<String> = None;
+        let mut search: Option<Search> = None;
+
+        self.input.enable_raw_mode()?;
+        self.print_lines(
+            &mut stdout,
+            &mut c,
+            &lines,
+            colour,
+            vi.as_ref(),
+            Decoration::None,
         )?;
 
-        loop {
-            if let event::Event::Key(e) = event::read()? {
-                match e.code {
-                    event::KeyCode::Char('c')
-                        if e.modifiers.contains(event::KeyModifiers::CONTROL) =>
-                    {
-                        self.exit()
+        let signal = loop {
+            if let event::Event::Key(e) = self.input.read()? {
+                let was_kill = std::mem::replace(&mut killed, false);
+                let was_yank = yanked.take();
+                let was_insert = std::mem::replace(&mut inserted, false);
+                let mut was_hint = hint.take();
+                let is_empty = !c.use_history && lines.len() == 1 && lines[0].is_empty();
+
+                // Keys which are handled by the vi bindings do not run any action, unless the
+                // command could not be handled within the lines
+                let mut action = if search.is_some() {
+                    self.search_key(e, &mut search, &mut c, &mut lines, &mut undo)
+                } else if e.code == event::KeyCode::Esc && menu.is_some() {
+                    // Esc goes back to what was typed before the completion menu was shown
+                    let (line, charno) = menu.take().unwrap().cancel();
+                    if self.cur_str(&c, &lines) != line {
+                        self.edit(&mut c, &mut lines, &mut undo);
+                        lines[c.lineno] = line;
+                    }
+                    c.charno = charno;
+                    None
+                } else {
+                    match vi.as_mut() {
+                        Some(vi) => match vi.key(e) {
+                            vi::Key::Pass => self.keymap.action(e),
+                            vi::Key::Pending => None,
+                            vi::Key::Undo => Some(Action::Undo),
+                            vi::Key::Redo => Some(Action::Redo),
+                            vi::Key::Run(command) if vi.changes(&command) => {
+                                self.edit(&mut c, &mut lines, &mut undo);
+                                vi.change::<L>(command, &mut lines, &mut c);
+                                // Text typed after a change such as `cw` is undone along with it
+                                inserted = vi.mode == vi::Mode::Insert;
+                                None
+                            }
+                            vi::Key::Run(command) => {
+                                vi.run::<L>(command, self.cur(&c, &lines), &mut c)
+                            }
+                        },
+                        None => self.keymap.action(e),
                     }
-                    event::KeyCode::Char('l')
-                        if e.modifiers.contains(event::KeyModifiers::CONTROL) =>
+                };
+
+                let mut submitted = false;
+                while let Some(Action::Custom(callback)) = action {
+                    let mut editor = Editor::new::<L>(self.cur(&c, &lines), c.lineno, c.charno);
+                    action = callback(&mut editor);
+                    submitted |= editor.is_submitted();
+                    self.apply(&mut stdout, &mut c, &mut lines, &mut undo, editor)?;
+                }
+
+                if submitted {
+                    break Signal::Command(self.cur(&c, &lines).join("\n"));
+                }
+
+                // Only presses of Ctrl-C one after the other are counted
+                if !matches!(action, Some(Action::Interrupt)) {
+                    self.interrupts = 0;
+                }
+
+                let action = match action {
+                    // Like readline, Ctrl-D deletes the character under the cursor if there is
+                    // any input
+                    Some(Action::Eof) if !is_empty => Some(Action::Delete),
+                    // In the indentation of a line, there is nothing to complete
+                    Some(Action::Complete)
+                        if menu.is_none()
+                            && self
+                                .cur_str(&c, &lines)
+                                .chars()
+                                .take(c.charno)
+                                .all(char::is_whitespace) =>
                     {
-                        let lineno = c.lineno;
-                        c.lineno = 0;
+                        Some(Action::Indent)
+                    }
+                    action => action,
+                };
+
+                // While the completion menu is shown, keys which move through it insert the
+                // candidates, and anything else closes it
+                let action = match menu.as_mut() {
+                    Some(m) => match action {
+                        Some(Action::Complete) | Some(Action::Down) => {
+                            if !m.is_selected() {
+                                self.edit(&mut c, &mut lines, &mut undo);
+                            }
+                            let (line, charno) = m.next();
+                            lines[c.lineno] = line;
+                            c.charno = charno;
+                            None
+                        }
+                        Some(Action::CompleteBackward) | Some(Action::Up) => {
+                            if !m.is_selected() {
+                                self.edit(&mut c, &mut lines, &mut undo);
+                            }
+                            let (line, charno) = m.prev();
+                            lines[c.lineno] = line;
+                            c.charno = charno;
+                            None
+                        }
+                        // Enter accepts the selected candidate, instead of giving the command
+                        Some(Action::Enter) if m.is_selected() => {
+                            menu = None;
+                            None
+                        }
+                        action => {
+                            menu = None;
+                            action
+                        }
+                    },
+                    None => action,
+                };
+
+                if let Some(action) = action {
+                    match action {
+                        Action::Undo => {
+                            self.undo_edit(&mut c, &mut lines, &mut undo);
+                        }
+                        Action::Redo => {
+                            self.redo_edit(&mut c, &mut lines, &mut undo);
+                        }
+                        Action::Interrupt => {
+                            match self.interrupt_policy {
+                                InterruptPolicy::Exit => break Signal::Exit,
+                                InterruptPolicy::ExitAfter(times) if is_empty => {
+                                    self.interrupts += 1;
+                                    if self.interrupts >= times {
+                                        break Signal::Exit;
+                                    }
+                                }
+                                _ => self.interrupts = 0,
+                            }
 
-                        queue!(
-                            stdout,
-                            terminal::Clear(terminal::ClearType::All),
-                            cursor::MoveTo(0, 0)
-                        )?;
-                        let lines = self.cur(&c, &lines);
-                        self.print_lines(&mut stdout, &mut c, lines, colour)?;
-                        c.lineno = lineno;
-                        c.charno = min(c.charno, lines[c.lineno].chars().count());
+                            break Signal::Interrupted;
+                        }
+                        Action::Eof => break Signal::Eof,
+                        Action::ClearScreen => {
+                            queue!(
+                                stdout,
+                                terminal::Clear(terminal::ClearType::All),
+                                cursor::MoveTo(0, 0)
+                            )?;
+                            c.row = 0;
+                        }
+                        Action::KillWordBackward => {
+                            self.edit(&mut c, &mut lines, &mut undo);
+
+                            let start = prev_word::<L>(&lines[c.lineno], c.charno);
+                            let text = remove_chars(&mut lines[c.lineno], start, c.charno);
+                            self.kill_ring.kill(text, Direction::Backward, was_kill);
+                            killed = true;
+                            c.charno = start;
+                        }
+                        Action::KillWordForward => {
+                            self.edit(&mut c, &mut lines, &mut undo);
 
-                        if c.lineno > 0 {
-                            queue!(stdout, cursor::MoveDown(lineno as u16))?;
+                            let end = next_word::<L>(&lines[c.lineno], c.charno);
+                            let text = remove_chars(&mut lines[c.lineno], c.charno, end);
+                            self.kill_ring.kill(text, Direction::Forward, was_kill);
+                            killed = true;
                         }
-                        queue!(
-                            stdout,
-                            cursor::MoveToColumn((self.continued_leader_len + c.charno) as u16),
-                        )?;
-                    }
-                    event::KeyCode::Char(chr) => {
-                        if c.use_history {
-                            self.replace_with_history(&mut lines);
-                            c.use_history = false;
-                        };
+                        Action::KillLineForward => {
+                            self.edit(&mut c, &mut lines, &mut undo);
 
-                        let byte_i = get_byte_i(&lines[c.lineno], c.charno);
+                            let end = lines[c.lineno].chars().count();
+                            let text = remove_chars(&mut lines[c.lineno], c.charno, end);
+                            self.kill_ring.kill(text, Direction::Forward, was_kill);
+                            killed = true;
+                        }
+                        Action::KillLineBackward => {
+                            self.edit(&mut c, &mut lines, &mut undo);
 
-                        lines[c.lineno].insert(byte_i, chr);
-                        c.charno += 1;
-                    }
-                    event::KeyCode::Tab => {
-                        if c.use_history {
-                            self.replace_with_history(&mut lines);
-                            c.use_history = false;
-                        };
-
-                        lines[c.lineno].insert_str(c.charno, "    ");
-                        c.charno += 4;
-                    }
+                            let text = remove_chars(&mut lines[c.lineno], 0, c.charno);
+                            self.kill_ring.kill(text, Direction::Backward, was_kill);
+                            killed = true;
+                            c.charno = 0;
+                        }
+                        Action::Yank => {
+                            if let Some(text) = self.kill_ring.yank() {
+                                let text = text.to_owned();
+                                self.edit(&mut c, &mut lines, &mut undo);
 
-                    event::KeyCode::Home => {
-                        c.charno = 0;
-                    }
-                    event::KeyCode::End => {
-                        c.charno = self.cur_str(&c, &lines).chars().count();
-                    }
-                    event::KeyCode::Left if c.charno > 0 => {
-                        c.charno -= 1;
-                    }
-                    event::KeyCode::Right => {
-                        if c.charno < self.cur_str(&c, &lines).chars().count() {
+                                let byte_i = get_byte_i(&lines[c.lineno], c.charno);
+                                lines[c.lineno].insert_str(byte_i, &text);
+
+                                let end = c.charno + text.chars().count();
+                                yanked = Some((c.charno, end));
+                                c.charno = end;
+                            }
+                        }
+                        Action::YankPop => {
+                            // Only the text which was just yanked can be replaced with an older
+                            // kill
+                            if let Some((start, end)) = was_yank {
+                                if let Some(text) = self.kill_ring.yank_pop() {
+                                    let text = text.to_owned();
+                                    self.edit(&mut c, &mut lines, &mut undo);
+                                    remove_chars(&mut lines[c.lineno], start, end);
+
+                                    let byte_i = get_byte_i(&lines[c.lineno], start);
+                                    lines[c.lineno].insert_str(byte_i, &text);
+
+                                    let end = start + text.chars().count();
+                                    yanked = Some((start, end));
+                                    c.charno = end;
+                                }
+                            }
+                        }
+                        Action::Insert(chr) => {
+                            // Characters typed one after the other are undone together
+                            if !was_insert {
+                                self.edit(&mut c, &mut lines, &mut undo);
+                            }
+                            inserted = true;
+
+                            let byte_i = get_byte_i(&lines[c.lineno], c.charno);
+
+                            lines[c.lineno].insert(byte_i, chr);
                             c.charno += 1;
-                        };
-                    }
+                        }
+                        Action::Complete => {
+                            menu = self.complete(&mut c, &mut lines, &mut undo);
+                        }
+                        Action::SearchBackward | Action::SearchForward => {
+                            let forward = matches!(action, Action::SearchForward);
+                            search = Some(Search::new(forward, (c.lineno, c.charno)));
+                        }
+                        Action::Indent => {
+                            self.edit(&mut c, &mut lines, &mut undo);
 
-                    event::KeyCode::PageUp => history_up!(retain self, stdout, c, lines, colour),
-                    // At the top of the current block, go to previous history block
-                    event::KeyCode::Up if c.lineno == 0 => {
-                        history_up!(self, stdout, c, lines, colour)
-                    }
-                    // In the middle of a block, go up one line
-                    event::KeyCode::Up => {
-                        c.lineno -= 1;
-                        queue!(stdout, cursor::MoveUp(1))?;
-                        c.charno = min(self.cur_str(&c, &lines).chars().count(), c.charno);
-                    }
+                            let byte_i = get_byte_i(&lines[c.lineno], c.charno);
 
-                    event::KeyCode::PageDown => {
-                        history_down!(retain self, stdout, c, lines, colour)
-                    }
-                    // At the bottom of the block, and in history. This means that there are more
-                    // blocks down, either further down the history or when history is over, the
-                    // editable lines itself
-                    event::KeyCode::Down
-                        if c.use_history && (c.lineno + 1) == self.history.cur().unwrap().len() =>
-                    {
-                        history_down!(self, stdout, c, lines, colour)
-                    }
-                    // When in the end of editable lines, nothing should be done
-                    event::KeyCode::Down if !c.use_history && (c.lineno + 1) == lines.len() => {}
-                    // Somewhere in the block, go to next line
-                    event::KeyCode::Down => {
-                        c.lineno += 1;
-                        queue!(stdout, cursor::MoveDown(1))?;
-                        c.charno = min(self.cur_str(&c, &lines).chars().count(), c.charno);
-                    }
+                            lines[c.lineno].insert_str(byte_i, "    ");
+                            c.charno += 4;
+                        }
 
-                    // Regular case, just need to delete a character
-                    event::KeyCode::Backspace if c.charno > 0 => {
-                        if c.use_history {
-                            self.replace_with_history(&mut lines);
-                            c.use_history = false;
-                        };
+                        // At the end of the input, the suggestion is accepted
+                        Action::Right | Action::LineEnd if was_hint.is_some() => {
+                            self.edit(&mut c, &mut lines, &mut undo);
 
-                        c.charno -= 1;
-                        let byte_i = get_byte_i(&lines[c.lineno], c.charno);
-                        lines[c.lineno].remove(byte_i);
-                    }
-                    // It is the last character, and it is not the last line
-                    event::KeyCode::Backspace if c.lineno > 0 => {
-                        if c.use_history {
-                            self.replace_with_history(&mut lines);
-                            c.use_history = false;
-                        };
-
-                        c.lineno -= 1;
-                        c.charno = lines[c.lineno].chars().count();
-                        let line = lines.remove(c.lineno + 1);
-                        lines[c.lineno] += &line;
-
-                        execute!(stdout, cursor::MoveUp(1))?;
-                        self.print_lines(&mut stdout, &mut c, &lines, colour)?;
-                    }
+                            let hint = was_hint.take().unwrap();
+                            let mut parts = hint.split('\n');
+                            // split always gives at least one item
+                            lines[c.lineno].push_str(parts.next().unwrap());
+                            lines.extend(parts.map(str::to_owned));
 
-                    // Regular delete, just need to delete one character
-                    event::KeyCode::Delete
-                        if c.charno < self.cur_str(&c, &lines).chars().count() =>
-                    {
-                        if c.use_history {
-                            self.replace_with_history(&mut lines);
-                            c.use_history = false;
-                        };
+                            c.lineno = lines.len() - 1;
+                            c.charno = lines[c.lineno].chars().count();
+                        }
+                        Action::LineStart => {
+                            c.charno = 0;
+                        }
+                        Action::LineEnd => {
+                            c.charno = self.cur_str(&c, &lines).chars().count();
+                        }
+                        Action::WordLeft => {
+                            c.charno = prev_word::<L>(self.cur_str(&c, &lines), c.charno);
+                        }
+                        Action::WordRight => {
+                            c.charno = next_word::<L>(self.cur_str(&c, &lines), c.charno);
+                        }
+                        Action::Left if c.charno > 0 => {
+                            c.charno -= 1;
+                        }
+                        Action::Right if c.charno < self.cur_str(&c, &lines).chars().count() => {
+                            c.charno += 1;
+                        }
 
-                        let byte_i = get_byte_i(&lines[c.lineno], c.charno);
-                        lines[c.lineno].remove(byte_i);
-                    }
-                    event::KeyCode::Delete if (c.lineno + 1) < self.cur(&c, &lines).len() => {
-                        if c.use_history {
-                            self.replace_with_history(&mut lines);
-                            c.use_history = false;
-                        };
+                        Action::HistoryUp => history_up!(retain self, c, lines),
+                        // At the top of the current block, go to previous history block
+                        Action::Up if c.lineno == 0 => {
+                            history_up!(self, c, lines)
+                        }
+                        // In the middle of a block, go up one line
+                        Action::Up => {
+                            c.lineno -= 1;
+                            c.charno = min(self.cur_str(&c, &lines).chars().count(), c.charno);
+                        }
 
-                        let line = lines.remove(c.lineno + 1);
-                        lines[c.lineno] += &line;
+                        Action::HistoryDown => {
+                            history_down!(retain self, c, lines)
+                        }
+                        // At the bottom of the block, and in history. This means that there are
+                        // more blocks down, either further down the history or when history is
+                        // over, the editable lines itself
+                        Action::Down
+                            if c.use_history
+                                && (c.lineno + 1) == self.history.cur().unwrap().len() =>
+                        {
+                            history_down!(self, c, lines)
+                        }
+                        // When in the end of editable lines, nothing should be done
+                        Action::Down if !c.use_history && (c.lineno + 1) == lines.len() => {}
+                        // Somewhere in the block, go to next line
+                        Action::Down => {
+                            c.lineno += 1;
+                            c.charno = min(self.cur_str(&c, &lines).chars().count(), c.charno);
+                        }
 
-                        self.print_lines(&mut stdout, &mut c, &lines, colour)?;
-                    }
+                        // Regular case, just need to delete a character
+                        Action::Backspace if c.charno > 0 => {
+                            self.edit(&mut c, &mut lines, &mut undo);
 
-                    event::KeyCode::Enter => {
-                        if self.cur(&c, &lines[..])[0].trim().is_empty() {
-                            execute!(
-                                stdout,
-                                cursor::MoveToNextLine(1),
-                                style::SetForegroundColor(colour),
-                                style::Print(self.leader)
-                            )?;
-                            // Empty line
-                            continue;
+                            c.charno -= 1;
+                            let byte_i = get_byte_i(&lines[c.lineno], c.charno);
+                            lines[c.lineno].remove(byte_i);
+                        }
+                        // It is the last character, and it is not the last line
+                        Action::Backspace if c.lineno > 0 => {
+                            self.edit(&mut c, &mut lines, &mut undo);
+
+                            c.lineno -= 1;
+                            c.charno = lines[c.lineno].chars().count();
+                            let line = lines.remove(c.lineno + 1);
+                            lines[c.lineno] += &line;
                         }
 
-                        if !c.use_history && lines.len() == 1 {
-                            if lines[0] == self.exit_keyword {
-                                self.exit();
-                            } else if lines[0] == self.clear_keyword {
-                                c.charno = 0;
-                                lines[0].clear();
+                        // Regular delete, just need to delete one character
+                        Action::Delete if c.charno < self.cur_str(&c, &lines).chars().count() => {
+                            self.edit(&mut c, &mut lines, &mut undo);
 
-                                execute!(
-                                    stdout,
-                                    terminal::Clear(terminal::ClearType::All),
-                                    cursor::MoveTo(0, 0),
-                                    style::SetForegroundColor(colour),
-                                    style::Print(self.leader),
-                                    style::ResetColor,
-                                )?;
-
-                                // Command executed, no need to do any other checks
-                                continue;
-                            }
+                            let byte_i = get_byte_i(&lines[c.lineno], c.charno);
+                            lines[c.lineno].remove(byte_i);
                         }
+                        Action::Delete if (c.lineno + 1) < self.cur(&c, &lines).len() => {
+                            self.edit(&mut c, &mut lines, &mut undo);
 
-                        if c.use_history && (c.lineno + 1) == self.history.cur().unwrap().len() {
-                            // On the last line, break out of loop to return code for execution
-                            break;
+                            let line = lines.remove(c.lineno + 1);
+                            lines[c.lineno] += &line;
                         }
-                        let indent = L::get_indent(&self.cur(&c, &lines)[0..(c.lineno + 1)]);
 
-                        if !c.use_history && (c.lineno + 1) == lines.len() && indent == 0 {
-                            // On the last line, break out of loop to return code for execution
-                            break;
-                        } else {
-                            if c.use_history {
-                                self.replace_with_history(&mut lines);
-                                c.use_history = false;
+                        // Empty line, the prompt is printed again on the next row
+                        Action::Enter if self.cur(&c, &lines)[0].trim().is_empty() => {
+                            if !c.use_history {
+                                c.charno = 0;
+                                lines[0].clear();
                             }
 
-                            c.lineno += 1;
-                            c.charno = indent;
-                            lines.insert(c.lineno, " ".repeat(indent));
-                            execute!(stdout, style::Print("\n"))?;
-                            self.print_lines(&mut stdout, &mut c, &lines, colour)?;
+                            // '\n' only moves down in raw mode, and scrolls if it is the last row
+                            queue!(stdout, style::Print("\r\n"))?;
+                            c.row = 0;
+                        }
+                        Action::Enter
+                            if !c.use_history
+                                && lines.len() == 1
+                                && lines[0] == self.exit_keyword =>
+                        {
+                            break Signal::Exit;
                         }
+                        Action::Enter
+                            if !c.use_history
+                                && lines.len() == 1
+                                && lines[0] == self.clear_keyword =>
+                        {
+                            c.charno = 0;
+                            lines[0].clear();
+                            undo = UndoStack::default();
+
+                            queue!(
+                                stdout,
+                                terminal::Clear(terminal::ClearType::All),
+                                cursor::MoveTo(0, 0)
+                            )?;
+                            c.row = 0;
+                        }
+                        Action::Enter => {
+                            if c.use_history && (c.lineno + 1) == self.history.cur().unwrap().len()
+                            {
+                                // On the last line, break out of loop to return code for execution
+                                break Signal::Command(self.cur(&c, &lines).join("\n"));
+                            }
+                            let indent = L::get_indent(&self.cur(&c, &lines)[0..(c.lineno + 1)]);
+
+                            if !c.use_history && (c.lineno + 1) == lines.len() && indent == 0 {
+                                // On the last line, break out of loop to return code for execution
+                                break Signal::Command(lines.join("\n"));
+                            } else {
+                                self.edit(&mut c, &mut lines, &mut undo);
+
+                                c.lineno += 1;
+                                c.charno = indent;
+                                lines.insert(c.lineno, " ".repeat(indent));
+                            }
+                        }
+                        _ => {}
                     }
-                    _ => {}
                 }
             };
 
-            queue!(
-                stdout,
-                terminal::Clear(terminal::ClearType::CurrentLine),
-                cursor::MoveToColumn(0),
-                style::SetForegroundColor(colour),
+            hint = if menu.is_none() && search.is_none() && self.suggestions {
+                self.hint(&c, &lines)
+            } else {
+                None
+            };
+            let decoration = match (&search, &menu, &hint) {
+                (Some(search), _, _) => Decoration::Search(search),
+                (None, Some(menu), _) => Decoration::Menu(menu),
+                (None, None, Some(hint)) => Decoration::Hint(hint),
+                (None, None, None) => Decoration::None,
+            };
+
+            // While searching, the entry which matches is shown
+            let cur = match search.as_ref().and_then(Search::found) {
+                Some(i) => &self.history[i],
+                None => self.cur(&c, &lines),
+            };
+            self.print_lines(&mut stdout, &mut c, cur, colour, vi.as_ref(), decoration)?;
+        };
+
+        // The suggestion and completion menu are removed, so that only the command is left
+        if c.decorated {
+            let cur = self.cur(&c, &lines);
+            self.print_lines(
+                &mut stdout,
+                &mut c,
+                cur,
+                colour,
+                vi.as_ref(),
+                Decoration::None,
             )?;
+        }
+
+        // Leave the cursor after the block, so that it is not overwritten by any further output
+        let below = self.cur(&c, &lines).len() - c.row - 1;
+        if below > 0 {
+            queue!(stdout, cursor::MoveDown(below as u16))?;
+        }
 
-            let (leader, leader_len) = if c.lineno == 0 {
-                (self.leader, self.leader_len)
+        if signal == Signal::Interrupted {
+            let last = self.cur(&c, &lines).len() - 1;
+            let leader_len = if last == 0 {
+                self.mode_indicator(vi.as_ref()).chars().count() + self.leader_len
             } else {
-                (self.continued_leader, self.continued_leader_len)
+                self.continued_leader_len
             };
+            let end = leader_len + self.cur(&c, &lines)[last].chars().count();
 
-            queue!(stdout, style::Print(leader))?;
-            L::print_line(&mut stdout, self.cur(&c, &lines[..]), c.lineno)?;
-            execute!(
+            queue!(
                 stdout,
-                cursor::MoveToColumn((leader_len + c.charno + 1) as u16)
+                cursor::MoveToColumn((end + 1) as u16),
+                style::Print("^C")
             )?;
+        } else {
+            self.interrupts = 0;
         }
 
-        terminal::disable_raw_mode()?;
-        println!();
+        self.input.disable_raw_mode()?;
+        stdout.write_all(b"\r\n")?;
+        self.history.reset_iter();
 
-        let src = self.cur(&c, &lines).join("\n");
+        Ok(signal)
+    }
 
-        if c.use_history {
-            self.history.push(self.history.cur().unwrap().clone());
-        } else {
-            self.history.push(lines);
-        }
+    /// Gives the next command from a non interactive input. Lines are read until the block is
+    /// complete according to [`LangInterface::get_indent`], without rendering anything. Like the
+    /// lines of a script, the commands are not added to history.
+    fn next_line(&mut self) -> crate::Result<Signal> {
+        loop {
+            let input = &mut self.input;
+            let lines = match read_block::<L, _>(|buf| input.read_line(buf))? {
+                Some(lines) => lines,
+                None => return Ok(Signal::Eof),
+            };
+
+            if lines.len() == 1 {
+                if lines[0] == self.exit_keyword {
+                    return Ok(Signal::Exit);
+                } else if lines[0] == self.clear_keyword {
+                    continue;
+                }
+            }
 
-        Ok(src)
+            return Ok(Signal::Command(lines.join("\n")));
+        }
     }
 }
 
-impl<L: LangInterface> Drop for Repl<L> {
+impl<L: LangInterface, I: InputSource, W: Write> Drop for Repl<L, I, W> {
     fn drop(&mut self) {
         self.pre_exit();
     }
@@ -520,9 +1476,159 @@ fn get_byte_i(string: &str, i: usize) -> usize {
         .unwrap_or_else(|| string.len())
 }
 
+/// What a [`Repl`] should do when Ctrl-C is pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptPolicy {
+    /// Discard the current input and give [`Signal::Interrupted`], like most shells do.
+    Cancel,
+    /// The same as `Cancel`, but give [`Signal::Exit`] when Ctrl-C is pressed this many times in a
+    /// row on an empty prompt.
+    ExitAfter(usize),
+    /// Always give [`Signal::Exit`].
+    Exit,
+}
+
+/// Which style of keys a [`Repl`] uses to edit commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditMode {
+    /// Keys like readline's default bindings, such as Ctrl-W, Ctrl-K and Ctrl-Y.
+    Emacs,
+    /// Keys like vi. Each command starts in insert mode, and Esc changes to normal mode, where
+    /// motions like `w` and `f<char>`, operators like `d`, `c` and `y`, counts and `.` can be used.
+    /// `v` and `V` start visual mode.
+    Vi,
+}
+
+/// Which history entries a [`Repl`] goes through with Up and Down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryNavigation {
+    /// Every entry, from the most recent one.
+    All,
+    /// Only entries which start with what has been typed, like zsh's
+    /// `history-beginning-search-backward`. When going back down past the most recent match, what
+    /// was typed is shown again.
+    Prefix,
+}
+
+/// Whether a [`Repl`] expands references to history in commands, like bash's history expansion.
+///
+/// | Reference        | Expands to                                                             |
+/// |------------------|------------------------------------------------------------------------|
+/// | `!!`             | The previous command                                                   |
+/// | `!n`             | The `n`th command in history, counting from 1 for the oldest           |
+/// | `!-n`            | The command `n` commands before this one, so `!-1` is `!!`             |
+/// | `!prefix`        | The most recent command which starts with `prefix`                     |
+/// | `!?text?`        | The most recent command which contains `text`                          |
+/// | `!$`, `!^`, `!*` | The last word, first argument or all arguments of the previous command |
+/// | `^old^new^`      | The previous command with the first `old` replaced by `new`            |
+///
+/// A word of any of the commands can be chosen by adding `:0` to `:9`, `:$`, `:^` or `:*`, for
+/// example `!-2:1`, where words are separated by whitespace and the first word is 0. Commands with
+/// more than one line are expanded to all of their lines.
+///
+/// A `!` is not expanded when it is escaped with `\`, inside single quotes, or followed by
+/// whitespace, `=` or a shell metacharacter like `;`, `|`, `(`, `)` or `"`. Like bash, it is
+/// expanded inside double quotes, and `!prefix` ends at any of these characters. If a reference
+/// cannot be expanded, the error is printed and the command can be edited again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryExpansion {
+    /// Commands are given back as they are typed.
+    Off,
+    /// References are expanded, and the expanded command is printed and given back.
+    On,
+    /// References are expanded, and the expanded command can be edited before it is run, like
+    /// bash's `histverify`.
+    Verify,
+}
+
+/// Which commands a [`Repl`] leaves out of history, like bash's `HISTCONTROL`. By default every
+/// command is kept.
+///
+/// It is applied when commands are run, and to the commands read from the history file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HistoryControl {
+    /// Leave out commands which start with a space, like `ignorespace`.
+    pub ignore_space: bool,
+    /// Leave out commands which are the same as the one before them, like `ignoredups`.
+    pub ignore_dups: bool,
+    /// Remove older copies of a command when it is run again, like `erasedups`.
+    pub erase_dups: bool,
+}
+
+/// The outcome of reading from a [`Repl`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signal {
+    /// A command was entered, and should be executed
+    Command(String),
+    /// The exit keyword was entered
+    Exit,
+    /// Ctrl-C was pressed, and the input was discarded. See [`InterruptPolicy`]
+    Interrupted,
+    /// Ctrl-D was pressed on an empty prompt, so there is no more input
+    Eof,
+}
+
+/// Gives the index of the start of the word before the character at index `i`, skipping any
+/// characters in between which are not part of a word.
+fn prev_word<L: LangInterface>(string: &str, i: usize) -> usize {
+    let chars: Vec<_> = string.chars().take(i).collect();
+    let mut i = chars.len();
+
+    while i > 0 && !L::is_word_char(chars[i - 1]) {
+        i -= 1;
+    }
+    while i > 0 && L::is_word_char(chars[i - 1]) {
+        i -= 1;
+    }
+
+    i
+}
+
+/// Gives the index of the end of the word after the character at index `i`, skipping any
+/// characters in between which are not part of a word.
+fn next_word<L: LangInterface>(string: &str, i: usize) -> usize {
+    let chars: Vec<_> = string.chars().skip(i).collect();
+    let mut j = 0;
+
+    while j < chars.len() && !L::is_word_char(chars[j]) {
+        j += 1;
+    }
+    while j < chars.len() && L::is_word_char(chars[j]) {
+        j += 1;
+    }
+
+    i + j
+}
+
+/// Removes the characters from index `from` to `to`, and gives them back
+fn remove_chars(string: &mut String, from: usize, to: usize) -> String {
+    let from = get_byte_i(string, from);
+    let to = get_byte_i(string, to);
+    string.drain(from..to).collect()
+}
+
 #[derive(Debug, Default)]
 struct Cursor {
     use_history: bool,
     lineno: usize,
     charno: usize,
+    /// The line of the printed block which the terminal cursor is on. It is only updated once the
+    /// block is printed again, so it can differ from `lineno` while handling a key.
+    row: usize,
+    /// Whether a suggestion or the completion menu was printed with the block
+    decorated: bool,
+    /// The number of rows printed below the block, for the rest of a multi-line suggestion or the
+    /// completion menu
+    below: usize,
+}
+
+/// What is printed along with the block of lines
+enum Decoration<'a> {
+    None,
+    /// Text suggested after the input, which is printed greyed out
+    Hint(&'a str),
+    /// The candidates to complete the text at the cursor, which are printed below the block
+    Menu(&'a Menu),
+    /// The search through history, whose prompt is printed instead of the leader
+    Search(&'a Search),
 }
@@ -0,0 +1,131 @@
+use super::get_byte_i;
+use crate::lang::Completion;
+
+/// The most candidates which are shown in the menu at once
+const HEIGHT: usize = 8;
+
+/// The menu shown below the prompt when there is more than one candidate to complete the text at
+/// the cursor.
+#[derive(Debug)]
+pub struct Menu {
+    candidates: Vec<Completion>,
+    /// The index of the candidate which has been inserted, if any has been
+    selected: Option<usize>,
+    /// The line the candidates were given for, which their ranges refer to
+    line: String,
+    /// The line and position of the cursor to go back to when the menu is cancelled
+    typed: (String, usize),
+}
+
+impl Menu {
+    pub fn new(candidates: Vec<Completion>, line: String, typed: (String, usize)) -> Self {
+        Self {
+            candidates,
+            selected: None,
+            line,
+            typed,
+        }
+    }
+
+    /// Whether a candidate has been inserted
+    pub fn is_selected(&self) -> bool {
+        self.selected.is_some()
+    }
+
+    /// Selects the next candidate, giving back the line with it inserted and the position of the
+    /// cursor after it
+    pub fn next(&mut self) -> (String, usize) {
+        let i = match self.selected {
+            Some(i) => (i + 1) % self.candidates.len(),
+            None => 0,
+        };
+        self.select(i)
+    }
+
+    /// Selects the previous candidate. See [`next`](Menu::next)
+    pub fn prev(&mut self) -> (String, usize) {
+        let i = match self.selected {
+            Some(0) | None => self.candidates.len() - 1,
+            Some(i) => i - 1,
+        };
+        self.select(i)
+    }
+
+    fn select(&mut self, i: usize) -> (String, usize) {
+        self.selected = Some(i);
+        complete(&self.line, &self.candidates[i])
+    }
+
+    /// Gives back the line and position of the cursor from before any candidate was selected
+    pub fn cancel(self) -> (String, usize) {
+        self.typed
+    }
+
+    /// The candidates which are shown and whether they are selected. At most [`HEIGHT`] are
+    /// shown, scrolling to keep the selected candidate visible.
+    pub fn visible(&self) -> impl Iterator<Item = (&str, bool)> {
+        let start = match self.selected {
+            Some(i) if i >= HEIGHT => i + 1 - HEIGHT,
+            _ => 0,
+        };
+        let selected = self.selected;
+
+        self.candidates
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(HEIGHT)
+            .map(move |(i, candidate)| (candidate.display(), Some(i) == selected))
+    }
+}
+
+/// Whether the range of the candidate is within the line and not reversed, so that it can be used
+pub fn is_valid(line: &str, candidate: &Completion) -> bool {
+    let range = &candidate.range;
+    range.start <= range.end && range.end <= line.chars().count()
+}
+
+/// Replaces the characters in the range of the candidate with it, giving back the new line and
+/// the position of the cursor after the candidate
+pub fn complete(line: &str, candidate: &Completion) -> (String, usize) {
+    let start = get_byte_i(line, candidate.range.start);
+    let end = get_byte_i(line, candidate.range.end);
+
+    let mut completed = String::with_capacity(line.len() + candidate.replacement.len());
+    completed.push_str(&line[..start]);
+    completed.push_str(&candidate.replacement);
+    completed.push_str(&line[end..]);
+
+    let charno = candidate.range.start + candidate.replacement.chars().count();
+    (completed, charno)
+}
+
+/// The text which all of the candidates start with, if they replace the same range and it only
+/// adds to what is already in the range
+pub fn common_prefix(line: &str, candidates: &[Completion]) -> Option<Completion> {
+    let first = candidates.first()?;
+    if candidates.iter().any(|c| c.range != first.range) {
+        return None;
+    }
+
+    let mut len = first.replacement.len();
+    for candidate in &candidates[1..] {
+        len = first
+            .replacement
+            .char_indices()
+            .zip(candidate.replacement.chars())
+            .take_while(|((i, a), b)| *i < len && a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0);
+    }
+    let prefix = &first.replacement[..len];
+
+    let start = get_byte_i(line, first.range.start);
+    let end = get_byte_i(line, first.range.end);
+    if prefix.len() > end - start && prefix.starts_with(&line[start..end]) {
+        Some(Completion::new(prefix, first.range.clone()))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,145 @@
+use super::get_byte_i;
+use crate::lang::LangInterface;
+use std::cmp::min;
+
+/// A handle to the command being edited, given to [`Action::Custom`](crate::Action::Custom)
+/// callbacks.
+///
+/// The lines and cursor can be read and changed, and once the callback returns, the changes are
+/// shown and can be undone like any other edit. Text can also be printed above the prompt, and the
+/// command can be given back from [`Repl::next`](crate::Repl::next) without pressing enter.
+///
+/// ```
+/// use shelp::{Action, Keymap};
+/// use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+///
+/// let mut keymap = Keymap::default();
+/// // F1 shows help for the word under the cursor
+/// keymap.bind(
+///     KeyEvent::new(KeyCode::F(1), KeyModifiers::NONE),
+///     Action::custom(|editor| {
+///         let word = editor.word();
+///         editor.print(format!("help for '{}'", word));
+///         None
+///     }),
+/// );
+/// ```
+#[derive(Debug)]
+pub struct Editor {
+    lines: Vec<String>,
+    lineno: usize,
+    charno: usize,
+    /// Text to print above the prompt
+    printed: Vec<String>,
+    submitted: bool,
+    is_word_char: fn(char) -> bool,
+}
+
+impl Editor {
+    pub(crate) fn new<L: LangInterface>(lines: &[String], lineno: usize, charno: usize) -> Self {
+        let mut editor = Self {
+            lines: lines.to_vec(),
+            lineno: 0,
+            charno: 0,
+            printed: Vec::new(),
+            submitted: false,
+            is_word_char: L::is_word_char,
+        };
+        editor.set_cursor(lineno, charno);
+        editor
+    }
+
+    /// The lines of the command
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// The line the cursor is on
+    pub fn line(&self) -> &str {
+        &self.lines[self.lineno]
+    }
+
+    /// The position of the cursor as `(lineno, charno)`, where `charno` is counted in characters
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.lineno, self.charno)
+    }
+
+    /// Moves the cursor, keeping it within the lines
+    pub fn set_cursor(&mut self, lineno: usize, charno: usize) {
+        self.lineno = min(lineno, self.lines.len() - 1);
+        self.charno = min(charno, self.lines[self.lineno].chars().count());
+    }
+
+    /// Replaces the lines of the command, keeping the cursor within them. If no lines are given, a
+    /// single empty line is used.
+    pub fn set_lines(&mut self, lines: Vec<String>) {
+        self.lines = lines;
+        if self.lines.is_empty() {
+            self.lines.push(String::new());
+        }
+        self.set_cursor(self.lineno, self.charno);
+    }
+
+    /// Inserts text at the cursor, and moves the cursor after it. A `'\n'` in the text starts a
+    /// new line.
+    pub fn insert(&mut self, text: &str) {
+        let line = &mut self.lines[self.lineno];
+        let after = line.split_off(get_byte_i(line, self.charno));
+
+        let mut inserted = text.split('\n');
+        // split always gives at least one item
+        line.push_str(inserted.next().unwrap());
+
+        for new_line in inserted {
+            self.lineno += 1;
+            self.lines.insert(self.lineno, new_line.to_owned());
+        }
+
+        let line = &mut self.lines[self.lineno];
+        self.charno = line.chars().count();
+        line.push_str(&after);
+    }
+
+    /// The word the cursor is in or just after, according to
+    /// [`LangInterface::is_word_char`]. If there is none, an empty string is given.
+    pub fn word(&self) -> &str {
+        let line = self.line();
+        let chars: Vec<_> = line.chars().collect();
+
+        let mut start = self.charno;
+        while start > 0 && (self.is_word_char)(chars[start - 1]) {
+            start -= 1;
+        }
+        let mut end = self.charno;
+        while end < chars.len() && (self.is_word_char)(chars[end]) {
+            end += 1;
+        }
+
+        &line[get_byte_i(line, start)..get_byte_i(line, end)]
+    }
+
+    /// Prints text above the prompt. It is printed once the callback returns, after anything
+    /// printed before it.
+    pub fn print<S: Into<String>>(&mut self, text: S) {
+        self.printed.push(text.into());
+    }
+
+    /// Gives back the command from [`Repl::next`](crate::Repl::next) once the callback returns,
+    /// even if it is not complete.
+    pub fn submit(&mut self) {
+        self.submitted = true;
+    }
+
+    /// Whether [`submit`](Editor::submit) was called
+    pub fn is_submitted(&self) -> bool {
+        self.submitted
+    }
+
+    pub(crate) fn take_printed(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.printed)
+    }
+
+    pub(crate) fn into_lines(self) -> Vec<String> {
+        self.lines
+    }
+}
@@ -0,0 +1,179 @@
+use super::history::History;
+
+/// Expands references to history in a command, like `!!` and `^old^new`. See
+/// [`HistoryExpansion`](crate::HistoryExpansion) for what is expanded.
+///
+/// If nothing is expanded, `None` is given. If a reference cannot be expanded, an error saying why
+/// is given.
+pub fn expand(command: &str, history: &History) -> Result<Option<String>, String> {
+    if let Some(substitution) = command.strip_prefix('^') {
+        return substitute(substitution, history).map(Some);
+    }
+
+    let chars: Vec<_> = command.chars().collect();
+    let mut expanded = String::with_capacity(command.len());
+    let mut is_expanded = false;
+    let mut single_quoted = false;
+    let mut double_quoted = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let chr = chars[i];
+        match chr {
+            // Like in a shell, quotes of one kind are left as they are inside quotes of the other
+            '\'' if !double_quoted => single_quoted = !single_quoted,
+            '"' if !single_quoted => double_quoted = !double_quoted,
+            // An escaped '!' is left as it is
+            '\\' if chars.get(i + 1) == Some(&'!') => {
+                expanded.push_str("\\!");
+                i += 2;
+                continue;
+            }
+            '!' if !single_quoted && is_designator_start(chars.get(i + 1)) => {
+                let (text, len) = reference(&chars[i..], history)?;
+                expanded.push_str(&text);
+                is_expanded = true;
+                i += len;
+                continue;
+            }
+            _ => {}
+        }
+
+        expanded.push(chr);
+        i += 1;
+    }
+
+    Ok(if is_expanded { Some(expanded) } else { None })
+}
+
+/// Like bash, a `!` followed by whitespace, `=`, a shell metacharacter or nothing is not expanded
+fn is_designator_start(chr: Option<&char>) -> bool {
+    match chr {
+        None | Some('=') => false,
+        Some(&chr) => !chr.is_whitespace() && !is_metachar(chr),
+    }
+}
+
+/// Characters which end a word in a shell, so that `!prefix` stops before them
+fn is_metachar(chr: char) -> bool {
+    matches!(
+        chr,
+        ';' | '&' | '|' | '(' | ')' | '<' | '>' | '"' | '\'' | '`'
+    )
+}
+
+/// Expands the reference at the start of `chars`, which starts with `!`, giving the text it expands
+/// to and the number of characters in it
+fn reference(chars: &[char], history: &History) -> Result<(String, usize), String> {
+    let text = |len: usize| chars[..len].iter().collect::<String>();
+    let not_found = |len| format!("{}: event not found", text(len));
+
+    let mut len = 2;
+    let mut word = None;
+    let entry = match chars[1] {
+        '!' => history.get(0),
+        '$' | '^' | '*' => {
+            word = Some(chars[1]);
+            history.get(0)
+        }
+        '-' | '0'..='9' => {
+            // `!-n` counts back from the most recent command, and `!n` forward from the oldest
+            let back = chars[1] == '-';
+            let start = if back { 2 } else { 1 };
+            len = start
+                + chars[start..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .count();
+            let n: usize = text(len)[start..].parse().map_err(|_| not_found(len))?;
+
+            if n == 0 {
+                None
+            } else if back {
+                history.get(n - 1)
+            } else {
+                history.len().checked_sub(n).and_then(|i| history.get(i))
+            }
+        }
+        '?' => {
+            let query_len = chars[2..]
+                .iter()
+                .take_while(|&&c| c != '?' && c != '\n')
+                .count();
+            let query: String = chars[2..2 + query_len].iter().collect();
+            len = 2 + query_len;
+            if chars.get(len) == Some(&'?') {
+                len += 1;
+            }
+            history
+                .iter()
+                .find(|entry| entry.lines().iter().any(|line| line.contains(&query)))
+        }
+        _ => {
+            len = 1 + chars[1..]
+                .iter()
+                .take_while(|&&c| !c.is_whitespace() && c != ':' && !is_metachar(c))
+                .count();
+            let prefix = text(len)[1..].to_owned();
+            history
+                .iter()
+                .find(|entry| entry.command().starts_with(&prefix))
+        }
+    };
+    let command = entry.ok_or_else(|| not_found(len))?.command();
+
+    if word.is_none() && chars.get(len) == Some(&':') {
+        match chars.get(len + 1) {
+            Some(&chr) if chr == '$' || chr == '^' || chr == '*' || chr.is_ascii_digit() => {
+                word = Some(chr);
+                len += 2;
+            }
+            _ => return Err(format!("{}: bad word specifier", text(len + 1))),
+        }
+    }
+
+    let text = match word {
+        Some(designator) => select_word(&command, designator)
+            .ok_or_else(|| format!("{}: bad word specifier", text(len)))?,
+        None => command,
+    };
+    Ok((text, len))
+}
+
+/// Gives the words of the command which `designator` refers to. Words are separated by whitespace,
+/// and the first word is numbered 0.
+fn select_word(command: &str, designator: char) -> Option<String> {
+    let words: Vec<_> = command.split_whitespace().collect();
+    match designator {
+        '$' => words.last().map(|&word| word.to_owned()),
+        '^' => words.get(1).map(|&word| word.to_owned()),
+        '*' => Some(words.get(1..).unwrap_or_default().join(" ")),
+        n => words
+            .get(n.to_digit(10)? as usize)
+            .map(|&word| word.to_owned()),
+    }
+}
+
+/// Expands `^old^new^`, which is the previous command with the first `old` replaced by `new`.
+/// Anything after the last `^` is added to the end, and the last `^` can be left out.
+fn substitute(substitution: &str, history: &History) -> Result<String, String> {
+    let failed = || format!("^{}: substitution failed", substitution);
+
+    let (old, rest) = match substitution.find('^') {
+        Some(i) => (&substitution[..i], &substitution[i + 1..]),
+        None => (substitution, ""),
+    };
+    let (new, tail) = match rest.find('^') {
+        Some(i) => (&rest[..i], &rest[i + 1..]),
+        None => (rest, ""),
+    };
+
+    let previous = history.get(0).ok_or_else(failed)?.command();
+    if old.is_empty() || !previous.contains(old) {
+        return Err(failed());
+    }
+
+    let mut expanded = previous.replacen(old, new, 1);
+    expanded.push_str(tail);
+    Ok(expanded)
+}
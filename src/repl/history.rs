@@ -1,22 +1,135 @@
+use super::store::{FileStore, HistoryStore};
+use super::HistoryControl;
 use std::cell::Cell;
-use std::collections::VecDeque;
-use std::fs;
-use std::io::{self, prelude::*};
+use std::collections::{HashSet, VecDeque};
+use std::io;
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Information about when and how a command in history was run. Any of it may not be known, for
+/// example if the command was read from an older history file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// When the command was given back from [`Repl::next`](crate::Repl::next)
+    pub time: Option<SystemTime>,
+    /// How long the command took to run
+    pub duration: Option<Duration>,
+    /// Whether the command succeeded, as reported with
+    /// [`Repl::report_status`](crate::Repl::report_status)
+    pub success: Option<bool>,
+    /// The working directory when the command was run
+    pub cwd: Option<PathBuf>,
+}
+
+impl Metadata {
+    /// Metadata for a command which is being run now
+    pub fn now() -> Self {
+        Self {
+            time: Some(SystemTime::now()),
+            duration: None,
+            success: None,
+            cwd: std::env::current_dir().ok(),
+        }
+    }
+}
+
+/// A command in history, along with its metadata
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    lines: Vec<String>,
+    metadata: Metadata,
+    saved: Saved,
+}
+
+impl HistoryEntry {
+    /// An entry for a command, as it is given by a [`HistoryStore`]
+    pub fn new(command: &str, metadata: Metadata) -> Self {
+        Self::with_saved(split_lines(command), metadata, Saved::Yes)
+    }
+
+    fn with_saved(lines: Vec<String>, metadata: Metadata, saved: Saved) -> Self {
+        Self {
+            lines,
+            metadata,
+            saved,
+        }
+    }
+
+    /// The lines of the command
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// The command, with its lines joined by `'\n'`, as it was given back from
+    /// [`Repl::next`](crate::Repl::next)
+    pub fn command(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    /// When and how the command was run
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// The whole seconds since the unix epoch when the command was run, which is how precisely the
+    /// time is kept in the history file
+    fn secs(&self) -> Option<u64> {
+        self.metadata
+            .time
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+    }
+
+    /// Whether the entries are the same command, run at the same time
+    fn is_same(&self, other: &HistoryEntry) -> bool {
+        self.lines == other.lines && self.secs() == other.secs()
+    }
+}
+
+/// Whether an entry is in the history store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Saved {
+    No,
+    Yes,
+    /// It is in the file, but its metadata has changed since it was written
+    Outdated,
+    /// It is only kept for this session, and is never saved
+    Never,
+}
+
+/// Gives whether a command should be kept in history
+pub type Filter = Box<dyn Fn(&str) -> bool>;
 
 /// Maintains REPL history of previously executed commands
 ///
 /// NOTE: The commands need not have executed successfully.
+///
+/// It is given by [`Repl::history`](crate::Repl::history) and
+/// [`Repl::history_mut`](crate::Repl::history_mut), so that commands can be listed, removed or
+/// added by the application, for example to implement a `history` builtin.
+///
+/// ```
+/// use shelp::{Metadata, Repl};
+///
+/// let mut repl = Repl::newd("> ", ". ", None);
+/// let history = repl.history_mut();
+/// history.push("let a = 2", Metadata::default());
+/// history.push("print a", Metadata::default());
+///
+/// let commands: Vec<_> = history.iter().rev().map(|entry| entry.command()).collect();
+/// assert_eq!(commands, ["let a = 2", "print a"]);
+/// ```
 pub struct History {
     /// The underlying buffer of history.
-    /// Each command is stored as [Vec<String>] where each String refers to a line.
+    /// Each command is stored as [Vec<String>] where each String refers to a line, along with its
+    /// [`Metadata`].
     ///
     /// The list of all commands is stored in a [VecDeque] since it must be allowed to insert and
     /// pop efficiently in **opposite** directions. It stores the history in reverse, since index 0
     /// is meant to be the previously executed command and index 1 the one before that and so on.
     /// So it must be efficient to push commands to the front of the buffer without recopying
     /// everything.
-    buffer: VecDeque<Vec<String>>,
+    buffer: VecDeque<HistoryEntry>,
     /// An index for the current position in history for ease of use.
     ///
     /// The `next()`, `prev()` and `cur()` functions operate on this index.
@@ -25,114 +138,468 @@ pub struct History {
     /// There is a need for a state where no history is in currently being used. For that state, -1
     /// is used.
     iter_i: Cell<isize>,
-    /// File to persist the history
-    path: Option<PathBuf>,
+    /// The most commands which are kept, both in the buffer and in the store
+    capacity: usize,
+    /// Where the history is persisted
+    store: Option<Box<dyn HistoryStore>>,
+    /// The number of commands known to be in the store, so that it can be appended to while it
+    /// stays within the capacity
+    stored: usize,
+    /// Whether the store could not be read, in which case it should not be overwritten
+    unreadable: bool,
+    /// Whether commands which are known to have failed are skipped when going through history
+    skip_failed: bool,
+    /// Which commands are left out of history
+    control: HistoryControl,
+    /// Gives whether a command should be kept in history
+    filter: Option<Filter>,
+    /// Entries which have been removed since the history was saved, which should be removed from
+    /// the store as well
+    removed: Vec<HistoryEntry>,
+    /// Whether the most recent command is the last one given to [`push`](History::push), so that
+    /// its status can be reported. It is not if that command was left out of history.
+    last_pushed: bool,
 }
 
 impl History {
-    pub fn with_capacity(capacity: usize, path: Option<PathBuf>) -> Self {
+    pub(crate) fn with_capacity(capacity: usize, path: Option<PathBuf>) -> Self {
         Self {
             buffer: VecDeque::with_capacity(capacity + 1),
             iter_i: Cell::new(-1),
-            path,
+            capacity,
+            store: path.map(|path| Box::new(FileStore::new(path)) as Box<dyn HistoryStore>),
+            stored: 0,
+            unreadable: false,
+            skip_failed: false,
+            control: HistoryControl::default(),
+            filter: None,
+            removed: Vec::new(),
+            last_pushed: false,
         }
     }
 
     fn at_capacity(&self) -> bool {
-        self.buffer.len() == self.buffer.capacity()
+        self.buffer.len() >= self.capacity
+    }
+
+    /// Adds a command which is being run now
+    pub(crate) fn push_lines(&mut self, lines: Vec<String>) {
+        self.push_entry(HistoryEntry::with_saved(lines, Metadata::now(), Saved::No));
     }
 
-    pub fn push(&mut self, lines: Vec<String>) {
+    /// Adds a command as the most recent one. It is left out if the
+    /// [`HistoryControl`](crate::HistoryControl) or filter of the repl say so, and the oldest
+    /// command is removed if history is full.
+    pub fn push(&mut self, command: &str, metadata: Metadata) {
+        self.push_entry(HistoryEntry::with_saved(
+            split_lines(command),
+            metadata,
+            Saved::No,
+        ));
+    }
+
+    /// Adds a command at `index`, where 0 is the most recent command. Unlike
+    /// [`push`](History::push), it is always added, but if history is full, the oldest command is
+    /// removed.
+    ///
+    /// The command is only kept for this session, for example to seed history with examples. It
+    /// is never saved to the store, and is left out when the history is reloaded.
+    ///
+    /// Panics if `index` is greater than [`len`](History::len).
+    pub fn insert(&mut self, index: usize, command: &str, metadata: Metadata) {
+        assert!(index <= self.len(), "index out of bounds");
+        self.reset_iter();
+        if index == 0 {
+            self.last_pushed = false;
+        }
+        self.buffer.insert(
+            index,
+            HistoryEntry::with_saved(split_lines(command), metadata, Saved::Never),
+        );
+        self.truncate();
+    }
+
+    /// Removes the command at `index`, where 0 is the most recent command. It is removed from the
+    /// store as well when the history is saved.
+    pub fn remove(&mut self, index: usize) -> Option<HistoryEntry> {
+        self.reset_iter();
+        let entry = self.buffer.remove(index)?;
+        if index == 0 {
+            self.last_pushed = false;
+        }
+        if matches!(entry.saved, Saved::Yes | Saved::Outdated) {
+            self.removed.push(entry.clone());
+        }
+        Some(entry)
+    }
+
+    /// Removes every command. They are removed from the store as well when the history is saved.
+    pub fn clear(&mut self) {
+        self.reset_iter();
+        self.last_pushed = false;
+        for entry in self.buffer.drain(..) {
+            if matches!(entry.saved, Saved::Yes | Saved::Outdated) {
+                self.removed.push(entry);
+            }
+        }
+    }
+
+    /// The most commands which are kept
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the most commands which are kept, removing the oldest ones if there are more. The
+    /// store is limited to the same number of commands when it is next changed, but the removed
+    /// commands are not removed from it otherwise.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.truncate();
+    }
+
+    fn truncate(&mut self) {
+        self.buffer.truncate(self.capacity);
+    }
+
+    /// The command at `index`, where 0 is the most recent command
+    pub fn get(&self, index: usize) -> Option<&HistoryEntry> {
+        self.buffer.get(index)
+    }
+
+    /// Iterates over the commands, starting from the most recent. Use `.rev()` to start from the
+    /// oldest instead.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> + ExactSizeIterator {
+        self.buffer.iter()
+    }
+
+    /// Finds the commands which contain `query`, starting from the most recent. The index of each
+    /// is given along with it, which can be given to [`get`](History::get) or
+    /// [`remove`](History::remove).
+    pub fn search<'a>(
+        &'a self,
+        query: &'a str,
+    ) -> impl Iterator<Item = (usize, &'a HistoryEntry)> + 'a {
+        self.iter()
+            .enumerate()
+            .filter(move |(_, entry)| entry.lines.iter().any(|line| line.contains(query)))
+    }
+
+    /// Saves the history to its store. It is done automatically when the repl is dropped, so this
+    /// is only needed to save it earlier. See [`Repl::set_incremental_history`] to save each
+    /// command as it is run.
+    ///
+    /// [`Repl::set_incremental_history`]: crate::Repl::set_incremental_history
+    pub fn save(&mut self) -> io::Result<()> {
+        self.write()
+    }
+
+    /// Reads the store again, so that commands saved to it by other sessions are included.
+    /// Commands from this session which have not been saved yet are kept, after the ones in the
+    /// store.
+    pub fn reload(&mut self) -> io::Result<()> {
+        let entries = match self.store_or_err()?.load() {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.unreadable = e.kind() == io::ErrorKind::InvalidData;
+                return Err(e);
+            }
+        };
+
+        self.unreadable = false;
+        self.stored = entries.len();
+        let entries = self.merged(entries);
+        self.reset_iter();
+        self.last_pushed = false;
+        self.buffer = entries.into_iter().rev().collect();
+        Ok(())
+    }
+
+    /// Where the history is persisted, if anywhere
+    pub fn store(&self) -> Option<&dyn HistoryStore> {
+        self.store.as_deref()
+    }
+
+    /// Where the history is persisted, if anywhere, for example to search every command in it
+    pub fn store_mut(&mut self) -> Option<&mut dyn HistoryStore> {
+        match &mut self.store {
+            Some(store) => Some(store.as_mut()),
+            None => None,
+        }
+    }
+
+    /// Changes where the history is persisted, and reads the commands from the new store. The
+    /// commands read from the previous store are replaced, but commands from this session which
+    /// have not been saved yet are kept, and will be saved to the new store.
+    pub(crate) fn set_store(&mut self, store: Box<dyn HistoryStore>) -> io::Result<()> {
+        self.buffer.retain(|entry| entry.saved != Saved::Yes);
+        for entry in &mut self.buffer {
+            if entry.saved == Saved::Outdated {
+                entry.saved = Saved::No;
+            }
+        }
+        self.removed.clear();
+        self.store = Some(store);
+        self.reload()
+    }
+
+    fn push_entry(&mut self, entry: HistoryEntry) {
+        self.reset_iter();
+        self.last_pushed = !self.is_ignored(&entry, self.buffer.front());
+        if !self.last_pushed {
+            return;
+        }
+        if self.control.erase_dups {
+            self.buffer.retain(|e| e.lines != entry.lines);
+        }
+
         // Make sure to not reallocate and keep within the capacity
         if self.at_capacity() {
             self.buffer.pop_back();
         }
 
         self.reset_iter();
-        self.buffer.push_front(lines);
-    }
-
-    // Each command is separated by a '---'
-    // So for example if there are 2 commands:
-    // ```
-    // let a = 2
-    // ```
-    // and
-    // ```
-    // if a < 2 {
-    //     a += 4
-    // }
-    // ```
-    //
-    // The history file produced would be:
-    // ```
-    // let a = 2
-    // ---
-    // if a < 2 {
-    //     a += 4
-    // }
-    // ---
-    // ```
-    /// Reads from history file and appends it to the current history buffer
-    pub fn read_from_file(&mut self) -> io::Result<()> {
-        let contents = fs::read_to_string(self.path.as_ref().ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, "Path to persisted file not found")
-        })?)?;
-        let mut lines = Vec::new();
-        for line in contents.lines() {
-            if line.starts_with("---") {
-                self.push(lines);
-                lines = Vec::new();
-            } else {
-                lines.push(line.to_owned());
+        self.buffer.push_front(entry);
+    }
+
+    pub(crate) fn set_control(&mut self, control: HistoryControl) {
+        self.control = control;
+        self.refilter();
+    }
+
+    pub(crate) fn set_filter(&mut self, filter: Filter) {
+        self.filter = Some(filter);
+        self.refilter();
+    }
+
+    /// Whether a command is left out of history, given the command before it
+    fn is_ignored(&self, entry: &HistoryEntry, previous: Option<&HistoryEntry>) -> bool {
+        let command = entry.lines.join("\n");
+
+        let is_dup = matches!(previous, Some(p) if p.lines == entry.lines);
+        let is_filtered = matches!(&self.filter, Some(filter) if !filter(&command));
+
+        (self.control.ignore_space && command.starts_with(' '))
+            || (self.control.ignore_dups && is_dup)
+            || is_filtered
+    }
+
+    /// Leaves out the commands which should not be kept, given from oldest to newest
+    fn filtered(&self, entries: impl IntoIterator<Item = HistoryEntry>) -> Vec<HistoryEntry> {
+        let mut kept: Vec<HistoryEntry> = Vec::new();
+        for entry in entries {
+            if self.is_ignored(&entry, kept.last()) {
+                continue;
+            }
+            if self.control.erase_dups {
+                kept.retain(|e| e.lines != entry.lines);
             }
+            kept.push(entry);
+        }
+        kept
+    }
+
+    /// Applies the control and filter to the commands already in history
+    fn refilter(&mut self) {
+        let entries = std::mem::take(&mut self.buffer).into_iter().rev();
+        let kept = self.filtered(entries);
+        self.buffer.extend(kept.into_iter().rev());
+        self.reset_iter();
+    }
+
+    /// Records whether the most recent command succeeded, and how long it took to run if it is not
+    /// given, as the time since it was run. Nothing is recorded if the command was left out of
+    /// history.
+    pub(crate) fn report_status(&mut self, success: bool, duration: Option<Duration>) {
+        if !self.last_pushed {
+            return;
+        }
+        if let Some(entry) = self.buffer.front_mut() {
+            let metadata = &mut entry.metadata;
+            metadata.success = Some(success);
+            metadata.duration = duration.or_else(|| metadata.time?.elapsed().ok());
+            if entry.saved == Saved::Yes {
+                entry.saved = Saved::Outdated;
+            }
+        }
+    }
+
+    /// Forgets the most recent command, so that a status is not reported for it once another
+    /// command has been read
+    pub(crate) fn reset_last_pushed(&mut self) {
+        self.last_pushed = false;
+    }
+
+    pub(crate) fn set_skip_failed(&mut self, skip_failed: bool) {
+        self.skip_failed = skip_failed;
+    }
+
+    fn is_skipped(&self, index: isize) -> bool {
+        self.skip_failed
+            && index >= 0
+            && self.buffer[index as usize].metadata.success == Some(false)
+    }
+
+    /// Reads the commands from the store and adds them to the history buffer. If the store cannot
+    /// be read, nothing is added and an error is given. The store will not be overwritten until it
+    /// is read successfully, so that it is not lost.
+    pub(crate) fn load(&mut self) -> io::Result<()> {
+        let entries = match self.store_or_err()?.load() {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.unreadable = e.kind() == io::ErrorKind::InvalidData;
+                return Err(e);
+            }
+        };
+
+        self.unreadable = false;
+        self.stored = entries.len();
+        for entry in entries {
+            self.push_entry(entry);
         }
         Ok(())
     }
 
-    /// Writes to the history path
-    pub fn write_to_file(&self) -> io::Result<()> {
-        let mut f = fs::File::create(self.path.as_ref().ok_or_else(|| {
-            io::Error::new(io::ErrorKind::NotFound, "Path to persisted file not found")
-        })?)?;
+    /// Saves the commands run since the history was last saved to the store.
+    ///
+    /// Other sessions may have saved commands to the same store, so instead of overwriting it, only
+    /// the changes from this session are made to it. If there are only new commands, they are
+    /// appended. Otherwise the commands in the store are updated with
+    /// [`HistoryStore::update`], which for a [`FileStore`] keeps other sessions from changing
+    /// it at the same time.
+    pub(crate) fn write(&mut self) -> io::Result<()> {
+        if self.unreadable {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "History could not be read, so it is not overwritten",
+            ));
+        }
+
+        let mut store = self.store.take().ok_or_else(no_store)?;
+        let result = self.write_to(store.as_mut());
+        self.store = Some(store);
+        result?;
 
-        for lines in self.buffer.iter().rev() {
-            for line in lines {
-                f.write_all(line.as_bytes())?;
-                f.write_all(b"\n")?;
+        for entry in &mut self.buffer {
+            if entry.saved != Saved::Never {
+                entry.saved = Saved::Yes;
             }
-            f.write_all(b"---\n")?;
         }
+        self.removed.clear();
 
         Ok(())
     }
 
+    fn write_to(&mut self, store: &mut dyn HistoryStore) -> io::Result<()> {
+        let new: Vec<_> = self
+            .buffer
+            .iter()
+            .rev()
+            .filter(|entry| entry.saved == Saved::No)
+            .cloned()
+            .collect();
+        let is_changed = !self.removed.is_empty()
+            || self
+                .buffer
+                .iter()
+                .any(|entry| entry.saved == Saved::Outdated);
+
+        if !is_changed && new.is_empty() {
+            Ok(())
+        } else if !is_changed
+            && !self.control.erase_dups
+            && self.stored + new.len() <= self.capacity
+        {
+            store.append(&new)?;
+            self.stored += new.len();
+            Ok(())
+        } else {
+            let mut stored = 0;
+            store.update(&mut |entries| {
+                let entries = self.merged(entries);
+                stored = entries.len();
+                entries
+            })?;
+            self.stored = stored;
+            Ok(())
+        }
+    }
+
+    /// The commands from the store, with the changes from this session applied to them: removed
+    /// commands are taken out, commands whose metadata changed are updated and new commands are
+    /// added to the end. They are given from oldest to newest, keeping only the most recent ones
+    /// within the capacity.
+    fn merged(&self, mut entries: Vec<HistoryEntry>) -> Vec<HistoryEntry> {
+        if !self.removed.is_empty() {
+            let removed: HashSet<_> = self
+                .removed
+                .iter()
+                .map(|entry| (&entry.lines, entry.secs()))
+                .collect();
+            entries.retain(|entry| !removed.contains(&(&entry.lines, entry.secs())));
+        }
+
+        for entry in self.buffer.iter().rev() {
+            match entry.saved {
+                Saved::Yes | Saved::Never => {}
+                Saved::Outdated => match entries.iter_mut().rev().find(|e| e.is_same(entry)) {
+                    Some(saved) => {
+                        saved.metadata = entry.metadata.clone();
+                        saved.saved = Saved::Outdated;
+                    }
+                    None => entries.push(entry.clone()),
+                },
+                Saved::No => entries.push(entry.clone()),
+            }
+        }
+
+        let mut entries = self.filtered(entries);
+        let start = entries.len().saturating_sub(self.capacity);
+        entries.drain(..start);
+        entries
+    }
+
+    fn store_or_err(&mut self) -> io::Result<&mut dyn HistoryStore> {
+        match &mut self.store {
+            Some(store) => Ok(store.as_mut()),
+            None => Err(no_store()),
+        }
+    }
+
     fn _len(&self) -> isize {
         self.buffer.len() as isize
     }
 
+    /// The number of commands in history
     pub fn len(&self) -> usize {
         self.buffer.len()
     }
 
+    /// Whether there are no commands in history
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
     fn _at(&self, index: isize) -> Option<&Vec<String>> {
         if index >= 0 {
-            Some(&self.buffer[index as usize])
+            Some(&self.buffer[index as usize].lines)
         } else {
             None
         }
     }
 
-    pub fn cur(&self) -> Option<&Vec<String>> {
-        if self.len() > 0 {
+    pub(crate) fn cur(&self) -> Option<&Vec<String>> {
+        if !self.is_empty() {
             self._at(self.iter_i.get())
         } else {
             None
         }
     }
 
-    pub fn prev(&self) -> Option<&Vec<String>> {
-        let iter_i = self.iter_i.get() + 1;
+    pub(crate) fn prev(&self) -> Option<&Vec<String>> {
+        let mut iter_i = self.iter_i.get() + 1;
+        while iter_i < self._len() && self.is_skipped(iter_i) {
+            iter_i += 1;
+        }
 
         if iter_i < self._len() {
             self.iter_i.set(iter_i);
@@ -142,8 +609,11 @@ impl History {
         }
     }
 
-    pub fn next(&self) -> Option<&Vec<String>> {
-        let iter_i = self.iter_i.get() - 1;
+    pub(crate) fn next(&self) -> Option<&Vec<String>> {
+        let mut iter_i = self.iter_i.get() - 1;
+        while self.is_skipped(iter_i) {
+            iter_i -= 1;
+        }
 
         // It is was already -1, so there definitely isn't a next to give.
         if iter_i >= -1 {
@@ -154,14 +624,35 @@ impl History {
         }
     }
 
-    pub fn reset_iter(&self) {
-        self.iter_i.set(-1);
+    /// Like [`prev`](History::prev), but skips commands which do not start with `prefix`. If there
+    /// is no such command, the position in history is not changed.
+    pub(crate) fn prev_matching(&self, prefix: &str) -> Option<&Vec<String>> {
+        let iter_i = self.iter_i.get();
+
+        while let Some(lines) = self.prev() {
+            if lines.join("\n").starts_with(prefix) {
+                return Some(lines);
+            }
+        }
+
+        self.iter_i.set(iter_i);
+        None
     }
 
-    #[allow(dead_code)]
-    pub fn clear(&mut self) {
-        self.buffer.clear();
-        self.reset_iter();
+    /// Like [`next`](History::next), but skips commands which do not start with `prefix`. If there
+    /// is no such command, history is no longer being used.
+    pub(crate) fn next_matching(&self, prefix: &str) -> Option<&Vec<String>> {
+        while let Some(lines) = self.next() {
+            if lines.join("\n").starts_with(prefix) {
+                return Some(lines);
+            }
+        }
+
+        None
+    }
+
+    pub(crate) fn reset_iter(&self) {
+        self.iter_i.set(-1);
     }
 }
 
@@ -169,12 +660,20 @@ impl std::ops::Index<usize> for History {
     type Output = Vec<String>;
 
     fn index(&self, index: usize) -> &Self::Output {
-        &self.buffer[index]
+        &self.buffer[index].lines
     }
 }
 
 impl Drop for History {
     fn drop(&mut self) {
-        let _ = self.write_to_file();
+        let _ = self.write();
     }
 }
+
+fn no_store() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "History is not persisted")
+}
+
+pub(crate) fn split_lines(command: &str) -> Vec<String> {
+    command.split('\n').map(str::to_owned).collect()
+}
@@ -1,18 +1,29 @@
-use super::Repl;
-use crate::LangInterface;
+use super::{
+    DefaultLangInterface, EditMode, HistoryControl, HistoryExpansion, HistoryNavigation,
+    InterruptPolicy, Repl, Signal,
+};
+use crate::{InputSource, Keymap, LangInterface, TerminalInput};
+use std::io::{self, Write};
 
 /// A wrapper over [`Repl`] which allows it to be used as a `Iterator`.
 ///
 /// Although using an iterator is easier, errors are discarded and [`None`] is returned. For this
 /// reason, it may be beneficial to use the [`Repl`] directly.
-pub struct ReplIter<L: LangInterface> {
-    repl: Repl<L>,
+///
+/// When the input is interrupted, the next command is read instead. Iteration ends when
+/// [`Signal::Exit`] or [`Signal::Eof`] is given by the [`Repl`].
+pub struct ReplIter<
+    L: LangInterface = DefaultLangInterface,
+    I: InputSource = TerminalInput,
+    W: Write = io::Stdout,
+> {
+    repl: Repl<L, I, W>,
     color: crate::Color,
 }
 
-impl<L: LangInterface> ReplIter<L> {
+impl<L: LangInterface, I: InputSource, W: Write> ReplIter<L, I, W> {
     /// Create a iterator for a [Repl]
-    pub fn new(repl: Repl<L>, color: crate::Color) -> Self {
+    pub fn new(repl: Repl<L, I, W>, color: crate::Color) -> Self {
         Self { repl, color }
     }
 
@@ -25,19 +36,75 @@ impl<L: LangInterface> ReplIter<L> {
     pub fn set_clear_keyword(&mut self, clear_keyword: &'static str) {
         self.repl.set_clear_keyword(clear_keyword)
     }
+
+    /// Sets what should be done when Ctrl-C is pressed
+    pub fn set_interrupt_policy(&mut self, interrupt_policy: InterruptPolicy) {
+        self.repl.set_interrupt_policy(interrupt_policy)
+    }
+
+    /// Sets what is done when each key is pressed
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.repl.set_keymap(keymap)
+    }
+
+    /// Sets whether text from history is suggested after the input
+    pub fn set_suggestions(&mut self, suggestions: bool) {
+        self.repl.set_suggestions(suggestions)
+    }
+
+    /// Sets which history entries are gone through with Up and Down
+    pub fn set_history_navigation(&mut self, history_navigation: HistoryNavigation) {
+        self.repl.set_history_navigation(history_navigation)
+    }
+
+    /// Sets whether commands which failed are skipped when going through history
+    pub fn set_skip_failed(&mut self, skip_failed: bool) {
+        self.repl.set_skip_failed(skip_failed)
+    }
+
+    /// Sets whether each command is saved to the history file as soon as it is run
+    pub fn set_incremental_history(&mut self, incremental_history: bool) {
+        self.repl.set_incremental_history(incremental_history)
+    }
+
+    /// Sets which commands are left out of history
+    pub fn set_history_control(&mut self, history_control: HistoryControl) {
+        self.repl.set_history_control(history_control)
+    }
+
+    /// Sets a filter which decides whether a command is kept in history
+    pub fn set_history_filter<F: Fn(&str) -> bool + 'static>(&mut self, filter: F) {
+        self.repl.set_history_filter(filter)
+    }
+
+    /// Sets whether references to history like `!!` are expanded in commands
+    pub fn set_history_expansion(&mut self, history_expansion: HistoryExpansion) {
+        self.repl.set_history_expansion(history_expansion)
+    }
+
+    /// Sets whether emacs style or vi style keys are used to edit commands
+    pub fn set_edit_mode(&mut self, edit_mode: EditMode) {
+        self.repl.set_edit_mode(edit_mode)
+    }
 }
 
-impl<L: LangInterface> Repl<L> {
+impl<L: LangInterface, I: InputSource, W: Write> Repl<L, I, W> {
     /// Shorthand to get iterator from self
-    pub fn iter(self, color: crate::Color) -> ReplIter<L> {
+    pub fn iter(self, color: crate::Color) -> ReplIter<L, I, W> {
         ReplIter::new(self, color)
     }
 }
 
-impl<L: LangInterface> Iterator for ReplIter<L> {
+impl<L: LangInterface, I: InputSource, W: Write> Iterator for ReplIter<L, I, W> {
     type Item = String;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.repl.next(self.color).ok()
+        loop {
+            match self.repl.next(self.color) {
+                Ok(Signal::Command(command)) => return Some(command),
+                Ok(Signal::Interrupted) => continue,
+                _ => return None,
+            }
+        }
     }
 }
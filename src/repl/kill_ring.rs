@@ -0,0 +1,75 @@
+use std::collections::VecDeque;
+
+/// Which way text was killed relative to the cursor.
+pub enum Direction {
+    /// The text after the cursor was killed, such as with `Ctrl-K`
+    Forward,
+    /// The text before the cursor was killed, such as with `Ctrl-U`
+    Backward,
+}
+
+/// Maintains text which has been killed (cut), so that it can be yanked (pasted) back.
+///
+/// Like readline, consecutive kills are joined into a single entry, and after yanking, the older
+/// entries can be cycled through.
+pub struct KillRing {
+    /// The killed text, where index 0 is the most recent kill.
+    buffer: VecDeque<String>,
+    /// The index of the entry which was last yanked.
+    yank_i: usize,
+    /// The most entries which are kept
+    capacity: usize,
+}
+
+impl KillRing {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buffer: VecDeque::with_capacity(capacity),
+            yank_i: 0,
+            capacity,
+        }
+    }
+
+    fn at_capacity(&self) -> bool {
+        self.buffer.len() >= self.capacity
+    }
+
+    /// Adds killed text to the ring. If `merge` is true, the previous action was also a kill, so
+    /// the text is joined with the most recent entry instead of creating a new one.
+    pub fn kill(&mut self, text: String, direction: Direction, merge: bool) {
+        if text.is_empty() {
+            return;
+        }
+
+        match self.buffer.front_mut() {
+            Some(last) if merge => match direction {
+                Direction::Forward => last.push_str(&text),
+                Direction::Backward => last.insert_str(0, &text),
+            },
+            _ => {
+                // Make sure to not reallocate and keep within the capacity
+                if self.at_capacity() {
+                    self.buffer.pop_back();
+                }
+                self.buffer.push_front(text);
+            }
+        }
+    }
+
+    /// Gives the most recently killed text
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yank_i = 0;
+        self.buffer.front().map(String::as_str)
+    }
+
+    /// Gives the text killed before the one which was last yanked, wrapping around to the most
+    /// recent once the oldest is reached
+    pub fn yank_pop(&mut self) -> Option<&str> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+
+        self.yank_i = (self.yank_i + 1) % self.buffer.len();
+        Some(&self.buffer[self.yank_i])
+    }
+}
@@ -0,0 +1,124 @@
+use super::history::History;
+
+/// A position in the lines as `(lineno, charno)`
+type Pos = (usize, usize);
+
+/// The state of an incremental search through history, started with Ctrl-R or Ctrl-S.
+#[derive(Debug)]
+pub struct Search {
+    query: String,
+    /// Whether newer entries are searched, like Ctrl-S, instead of older ones
+    forward: bool,
+    /// The index in history of the entry which matches, and the position of the match in it
+    found: Option<(usize, Pos)>,
+    /// Whether the last search did not find anything, in which case the previous match is kept
+    failed: bool,
+    /// The position of the cursor before searching, to go back to if nothing is found
+    cursor: Pos,
+}
+
+impl Search {
+    pub fn new(forward: bool, cursor: Pos) -> Self {
+        Self {
+            query: String::new(),
+            forward,
+            found: None,
+            failed: false,
+            cursor,
+        }
+    }
+
+    /// What is printed instead of the leader while searching
+    pub fn prompt(&self) -> String {
+        format!(
+            "({}{}i-search)`{}': ",
+            if self.failed { "failed " } else { "" },
+            if self.forward { "" } else { "reverse-" },
+            self.query
+        )
+    }
+
+    /// The index in history of the entry which matches
+    pub fn found(&self) -> Option<usize> {
+        self.found.map(|(i, _)| i)
+    }
+
+    /// Where the cursor should be, which is the start of the match if there is one
+    pub fn cursor(&self) -> Pos {
+        self.found.map(|(_, pos)| pos).unwrap_or(self.cursor)
+    }
+
+    /// The position of the cursor before searching
+    pub fn original_cursor(&self) -> Pos {
+        self.cursor
+    }
+
+    /// The first and last characters of the match, to be highlighted
+    pub fn selection(&self) -> Option<(Pos, Pos, bool)> {
+        let len = self.query.chars().count();
+        match self.found {
+            Some((_, (lineno, charno))) if len > 0 => {
+                Some(((lineno, charno), (lineno, charno + len - 1), false))
+            }
+            _ => None,
+        }
+    }
+
+    /// Adds a character to the query, and searches again from the current match
+    pub fn push(&mut self, chr: char, history: &History) {
+        self.query.push(chr);
+        let start = self.found().unwrap_or(0);
+        self.search(history, Some(start));
+    }
+
+    /// Removes the last character of the query, and searches again from the most recent entry
+    pub fn pop(&mut self, history: &History) {
+        self.query.pop();
+        if self.query.is_empty() {
+            self.found = None;
+            self.failed = false;
+        } else {
+            self.search(history, Some(0));
+        }
+    }
+
+    /// Searches for the next match after the current one, in the given direction
+    pub fn next(&mut self, forward: bool, history: &History) {
+        self.forward = forward;
+        if self.query.is_empty() {
+            return;
+        }
+
+        let start = match (self.found(), forward) {
+            (Some(i), false) => Some(i + 1),
+            (Some(i), true) => i.checked_sub(1),
+            (None, _) => Some(0),
+        };
+        self.search(history, start);
+    }
+
+    /// Finds the first entry from `start` in the current direction which contains the query
+    fn search(&mut self, history: &History, start: Option<usize>) {
+        let found = start
+            .filter(|&start| start < history.len())
+            .and_then(|start| {
+                let matches = |i: usize| {
+                    history[i].iter().enumerate().find_map(|(lineno, line)| {
+                        line.find(&self.query)
+                            .map(|byte_i| (i, (lineno, line[..byte_i].chars().count())))
+                    })
+                };
+
+                if self.forward {
+                    (0..=start).rev().find_map(matches)
+                } else {
+                    (start..history.len()).find_map(matches)
+                }
+            });
+
+        self.failed = found.is_none();
+        if found.is_some() {
+            self.found = found;
+        }
+    }
+}
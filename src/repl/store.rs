@@ -0,0 +1,442 @@
+use super::history::{HistoryEntry, Metadata};
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where the history of a [`Repl`](crate::Repl) is persisted. By default it is a [`FileStore`],
+/// but any store can be given with
+/// [`Repl::set_history_store`](crate::Repl::set_history_store), for example one which keeps
+/// history in memory for tests, or a history file for each project.
+///
+/// Commands are given to and from a store from oldest to newest.
+pub trait HistoryStore {
+    /// Reads every command in the store. If it cannot be read because it is invalid, an error of
+    /// kind [`InvalidData`](io::ErrorKind::InvalidData) should be given, so that it is not
+    /// overwritten.
+    fn load(&mut self) -> io::Result<Vec<HistoryEntry>>;
+
+    /// Adds commands after the ones in the store.
+    fn append(&mut self, entries: &[HistoryEntry]) -> io::Result<()>;
+
+    /// Replaces every command in the store.
+    fn rewrite(&mut self, entries: &[HistoryEntry]) -> io::Result<()>;
+
+    /// Finds the commands in the store which contain `query`, from newest to oldest. By default
+    /// every command is loaded and searched.
+    fn search(&mut self, query: &str) -> io::Result<Vec<HistoryEntry>> {
+        let mut found: Vec<_> = self
+            .load()?
+            .into_iter()
+            .filter(|entry| entry.lines().iter().any(|line| line.contains(query)))
+            .collect();
+        found.reverse();
+        Ok(found)
+    }
+
+    /// Replaces the commands in the store with what `update` gives back from the current ones.
+    /// This is used instead of [`rewrite`](HistoryStore::rewrite) when saving, so that commands
+    /// added by other sessions are not lost.
+    ///
+    /// By default the commands are loaded and then rewritten. A store which is shared between
+    /// sessions should keep them from changing it in between, like [`FileStore`] does.
+    fn update(
+        &mut self,
+        update: &mut dyn FnMut(Vec<HistoryEntry>) -> Vec<HistoryEntry>,
+    ) -> io::Result<()> {
+        let entries = self.load()?;
+        self.rewrite(&update(entries))
+    }
+}
+
+/// Keeps history in a text file, with a command on each line. This is what is used when a path is
+/// given to [`Repl::new`](crate::Repl::new).
+///
+/// While the file is being changed, a `.lock` file next to it is locked, so that other sessions
+/// sharing it do not change it at the same time. New commands are added to the end of the file.
+/// When it is rewritten, the new contents are written to a `.tmp` file which is then renamed to the
+/// history file, so that it is never left half written. The new file keeps the permissions of the
+/// old one, and if the path is a symlink, the file it links to is replaced rather than the link.
+///
+/// Files written by older versions, where each command is followed by a `---` line, can be read as
+/// well, and are written in the current format the next time they are changed.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    /// Create a store which keeps history in the file at `path`
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The path of the history file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replaces the history file with the commands, while the lock is held
+    fn write(&self, lock: &Lock, entries: &[HistoryEntry]) -> io::Result<()> {
+        let mut contents = format!("{}\n", HEADER);
+        contents.push_str(&format_entries(entries));
+        lock.replace(&contents)
+    }
+
+    /// Whether the history file exists and is in the current format, so that it can be appended to
+    fn is_current(&self) -> io::Result<bool> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        let mut header = String::new();
+        io::BufReader::new(file).read_line(&mut header)?;
+        Ok(header.trim_end() == HEADER)
+    }
+}
+
+impl HistoryStore for FileStore {
+    /// Reads the history file. If it does not exist, there are no commands.
+    fn load(&mut self) -> io::Result<Vec<HistoryEntry>> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => parse(&contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Only the new commands are written. If the file does not exist yet or is in an older format,
+    /// the whole file is written in the current one instead.
+    fn append(&mut self, entries: &[HistoryEntry]) -> io::Result<()> {
+        let lock = Lock::new(&self.path)?;
+        if self.is_current()? {
+            lock.append(&format_entries(entries))
+        } else {
+            let mut saved = self.load()?;
+            saved.extend_from_slice(entries);
+            self.write(&lock, &saved)
+        }
+    }
+
+    fn rewrite(&mut self, entries: &[HistoryEntry]) -> io::Result<()> {
+        let lock = Lock::new(&self.path)?;
+        self.write(&lock, entries)
+    }
+
+    fn update(
+        &mut self,
+        update: &mut dyn FnMut(Vec<HistoryEntry>) -> Vec<HistoryEntry>,
+    ) -> io::Result<()> {
+        let lock = Lock::new(&self.path)?;
+        let entries = update(self.load()?);
+        self.write(&lock, &entries)
+    }
+}
+
+/// The first line of a history file written by [`FileStore`], which gives the version of the format.
+///
+/// After it, each line is a command and its [`Metadata`], as
+/// `<time>;<duration>;<status>;<cwd>\t<command>`:
+/// - `time` is the number of seconds since the unix epoch when the command was run
+/// - `duration` is the number of milliseconds it took to run
+/// - `status` is `ok` if it succeeded or `failed` if it did not
+/// - `cwd` is the directory it was run in
+///
+/// Any field which is not known is left empty. In the directory and command, any `\`, tab, new
+/// line and carriage return are escaped as `\\`, `\t`, `\n` and `\r`. For example, the commands
+/// ```text
+/// let a = 2
+/// ```
+/// and
+/// ```text
+/// if a < 2 {
+///     a += 4
+/// }
+/// ```
+/// are written as
+/// ```text
+/// #shelp-history v2
+/// 1600000000;5;ok;/home/user\tlet a = 2
+/// 1600000010;;;/home/user\tif a < 2 {\n    a += 4\n}
+/// ```
+const HEADER: &str = "#shelp-history v2";
+/// The start of the first line of any versioned history file
+const HEADER_PREFIX: &str = "#shelp-history ";
+
+/// A lock on the history file, which is held by locking a file next to it with `.lock` added to
+/// its name. The lock is released by the OS when it is dropped, or if the session holding it
+/// crashes, so the lock file is left in place rather than being removed.
+struct Lock {
+    /// The lock file, which is locked for as long as this is kept
+    _file: fs::File,
+    /// The history file, with any symlinks followed
+    target: PathBuf,
+}
+
+impl Lock {
+    /// How long to wait for another session to release the lock
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// How many symlinks are followed to find the history file
+    const MAX_LINKS: usize = 40;
+
+    fn new(history_path: &Path) -> io::Result<Self> {
+        let target = Self::follow_links(history_path);
+        let file = private_file()
+            .write(true)
+            .open(with_suffix(&target, ".lock"))?;
+
+        let start = SystemTime::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => {
+                    return Ok(Self {
+                        _file: file,
+                        target,
+                    })
+                }
+                Err(fs::TryLockError::WouldBlock) => {
+                    if start
+                        .elapsed()
+                        .map_or(true, |waited| waited > Self::TIMEOUT)
+                    {
+                        return Err(io::Error::new(
+                            io::ErrorKind::WouldBlock,
+                            "History file is locked by another session",
+                        ));
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(fs::TryLockError::Error(e)) => return Err(e),
+            }
+        }
+    }
+
+    /// Follows symlinks from `path`, so that the file they link to is replaced rather than the link.
+    /// The target does not need to exist.
+    fn follow_links(path: &Path) -> PathBuf {
+        let mut path = path.to_owned();
+        for _ in 0..Self::MAX_LINKS {
+            match fs::read_link(&path) {
+                // A relative target is relative to the directory of the link
+                Ok(target) => path = path.parent().map_or(target.clone(), |dir| dir.join(target)),
+                Err(_) => break,
+            }
+        }
+        path
+    }
+
+    /// Adds to the end of the history file
+    fn append(&self, contents: &str) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new().append(true).open(&self.target)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_data()
+    }
+
+    /// Replaces the history file. The contents are written to a file with `.tmp` added to its name,
+    /// which is then renamed to the history file, so that it is never left half written.
+    fn replace(&self, contents: &str) -> io::Result<()> {
+        let path = with_suffix(&self.target, ".tmp");
+        let result = self
+            .write_new(&path, contents)
+            .and_then(|_| fs::rename(&path, &self.target));
+        if result.is_err() {
+            let _ = fs::remove_file(&path);
+        }
+        result
+    }
+
+    fn write_new(&self, path: &Path, contents: &str) -> io::Result<()> {
+        let mut file = private_file().write(true).truncate(true).open(path)?;
+        // The new file should not be readable by anyone who cannot read the history file
+        if let Ok(metadata) = fs::metadata(&self.target) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    }
+}
+
+/// Options to create a file which only its owner can read and write, if it does not exist
+fn private_file() -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    options.create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Parses the contents of a history file, giving the commands from oldest to newest
+fn parse(contents: &str) -> io::Result<Vec<HistoryEntry>> {
+    let mut lines = contents.lines().enumerate();
+
+    match lines.next() {
+        Some((_, HEADER)) => lines
+            .map(|(i, line)| parse_entry(line).map_err(|e| invalid_data(i + 1, e)))
+            .collect(),
+        Some((_, header)) if header.starts_with(HEADER_PREFIX) => Err(invalid_data(
+            1,
+            format!(
+                "unsupported history version '{}'",
+                &header[HEADER_PREFIX.len()..]
+            ),
+        )),
+        _ => Ok(parse_unversioned(contents)
+            .into_iter()
+            .map(|lines| HistoryEntry::new(&lines.join("\n"), Metadata::default()))
+            .collect()),
+    }
+}
+
+/// Writes the entries, each on its own line
+fn format_entries(entries: &[HistoryEntry]) -> String {
+    let mut contents = String::new();
+    for entry in entries {
+        contents.push_str(&format_entry(entry));
+        contents.push('\n');
+    }
+    contents
+}
+
+/// Writes an entry as a single line. See [`HEADER`] for the format
+fn format_entry(entry: &HistoryEntry) -> String {
+    let metadata = entry.metadata();
+    let mut line = String::new();
+
+    if let Some(since_epoch) = metadata
+        .time
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+    {
+        line.push_str(&since_epoch.as_secs().to_string());
+    }
+    line.push(';');
+    if let Some(duration) = metadata.duration {
+        line.push_str(&duration.as_millis().to_string());
+    }
+    line.push(';');
+    match metadata.success {
+        Some(true) => line.push_str("ok"),
+        Some(false) => line.push_str("failed"),
+        None => {}
+    }
+    line.push(';');
+    if let Some(cwd) = &metadata.cwd {
+        line.push_str(&escape(&cwd.to_string_lossy()));
+    }
+    line.push('\t');
+    line.push_str(&escape(&entry.command()));
+
+    line
+}
+
+/// Reads an entry written by [`format_entry`]
+fn parse_entry(line: &str) -> Result<HistoryEntry, String> {
+    let (metadata, command) = split_once(line, '\t').ok_or("missing command")?;
+
+    let mut fields = metadata
+        .splitn(4, ';')
+        .map(|field| Some(field).filter(|field| !field.is_empty()));
+    let mut next_field = || fields.next().flatten();
+    let (time, duration, success, cwd) = (next_field(), next_field(), next_field(), next_field());
+
+    let number = |field: Option<&str>| {
+        field
+            .map(|n| n.parse().map_err(|_| format!("invalid number '{}'", n)))
+            .transpose()
+    };
+
+    Ok(HistoryEntry::new(
+        &unescape(command)?,
+        Metadata {
+            time: number(time)?.map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            duration: number(duration)?.map(Duration::from_millis),
+            success: match success {
+                Some("ok") => Some(true),
+                Some("failed") => Some(false),
+                Some(status) => return Err(format!("invalid status '{}'", status)),
+                None => None,
+            },
+            cwd: cwd.map(unescape).transpose()?.map(PathBuf::from),
+        },
+    ))
+}
+
+fn split_once(s: &str, delimiter: char) -> Option<(&str, &str)> {
+    let i = s.find(delimiter)?;
+    Some((&s[..i], &s[i + delimiter.len_utf8()..]))
+}
+
+/// Parses a history file from before the format was versioned, where each command is followed by a
+/// line of `---`. Any lines after the last `---` are kept as a command as well.
+fn parse_unversioned(contents: &str) -> Vec<Vec<String>> {
+    let mut commands = Vec::new();
+    let mut lines = Vec::new();
+
+    for line in contents.lines() {
+        if line == "---" {
+            commands.push(std::mem::take(&mut lines));
+        } else {
+            lines.push(line.to_owned());
+        }
+    }
+
+    if !lines.is_empty() {
+        commands.push(lines);
+    }
+
+    commands
+}
+
+fn invalid_data(lineno: usize, error: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid history file, line {}: {}", lineno, error),
+    )
+}
+
+/// Escapes a command or path so that it fits on a single line, without any tabs
+fn escape(command: &str) -> String {
+    let mut escaped = String::with_capacity(command.len());
+    for chr in command.chars() {
+        match chr {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            chr => escaped.push(chr),
+        }
+    }
+    escaped
+}
+
+/// Gives back a command which was escaped with [`escape`]
+fn unescape(line: &str) -> Result<String, String> {
+    let mut command = String::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(chr) = chars.next() {
+        if chr != '\\' {
+            command.push(chr);
+            continue;
+        }
+
+        match chars.next() {
+            Some('\\') => command.push('\\'),
+            Some('t') => command.push('\t'),
+            Some('n') => command.push('\n'),
+            Some('r') => command.push('\r'),
+            Some(chr) => return Err(format!("unknown escape sequence '\\{}'", chr)),
+            None => return Err("unfinished escape sequence".to_owned()),
+        }
+    }
+
+    Ok(command)
+}
@@ -0,0 +1,48 @@
+/// The state of the lines being edited, which can be gone back to.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub lines: Vec<String>,
+    pub lineno: usize,
+    pub charno: usize,
+}
+
+impl Snapshot {
+    pub fn new(lines: &[String], lineno: usize, charno: usize) -> Self {
+        Self {
+            lines: lines.to_vec(),
+            lineno,
+            charno,
+        }
+    }
+}
+
+/// Keeps the states of the lines before each edit, so that edits can be undone and redone.
+#[derive(Debug, Default)]
+pub struct UndoStack {
+    /// The states before each edit, where the last one is the most recent.
+    undo: Vec<Snapshot>,
+    /// The states which have been undone, where the last one is the most recently undone.
+    redo: Vec<Snapshot>,
+}
+
+impl UndoStack {
+    /// Saves the state before an edit. Anything which was undone can no longer be redone.
+    pub fn push(&mut self, snapshot: Snapshot) {
+        self.undo.push(snapshot);
+        self.redo.clear();
+    }
+
+    /// Gives the state before the last edit, saving `current` so that it can be redone.
+    pub fn undo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let snapshot = self.undo.pop()?;
+        self.redo.push(current);
+        Some(snapshot)
+    }
+
+    /// Gives the state before the last undo, saving `current` so that it can be undone again.
+    pub fn redo(&mut self, current: Snapshot) -> Option<Snapshot> {
+        let snapshot = self.redo.pop()?;
+        self.undo.push(current);
+        Some(snapshot)
+    }
+}
@@ -0,0 +1,1171 @@
+use super::{get_byte_i, remove_chars, Cursor};
+use crate::keymap::Action;
+use crate::lang::LangInterface;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::cmp::min;
+
+/// A position in the lines as `(lineno, charno)`
+type Pos = (usize, usize);
+
+/// The mode the editor is in when using vi bindings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Keys are typed into the lines, like when not using vi bindings
+    Insert,
+    /// Keys are commands which move the cursor and edit the lines
+    Normal,
+    /// Like normal mode, but operators act on the text between where visual mode was started and
+    /// the cursor. If `linewise` is true, whole lines are selected.
+    Visual { linewise: bool },
+}
+
+/// What should be done with a key given to [`Vi::key`].
+pub enum Key {
+    /// The key should be handled as it would be without vi bindings
+    Pass,
+    /// The key is part of a command which has not been finished yet
+    Pending,
+    Undo,
+    Redo,
+    /// A command was finished, and should be given to [`Vi::change`] if [`Vi::changes`] says that
+    /// it changes the lines, or [`Vi::run`] otherwise
+    Run(Command),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Find {
+    chr: char,
+    forward: bool,
+    /// Whether the cursor stops just before the character, like `t` and `T`
+    till: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    /// `w`, or `W` if true
+    WordStart(bool),
+    /// `b`, or `B` if true
+    WordBack(bool),
+    /// `e`, or `E` if true
+    WordEnd(bool),
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    Find(Find),
+    /// `;`, or `,` if true
+    RepeatFind(bool),
+    FirstLine,
+    LastLine,
+}
+
+/// How the text between the cursor and the end of a motion is used by an operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+    ToggleCase,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Insert {
+    /// `i`
+    Before,
+    /// `a`
+    After,
+    /// `I`
+    LineStart,
+    /// `A`
+    LineEnd,
+    /// `o`
+    LineBelow,
+    /// `O`
+    LineAbove,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommandKind {
+    Move(Motion),
+    Operate(Operator, Motion),
+    /// An operator which is repeated, like `dd`, acts on whole lines
+    Lines(Operator),
+    /// An operator in visual mode acts on the selection
+    Selection(Operator),
+    Insert(Insert),
+    Put {
+        before: bool,
+    },
+    Replace(char),
+    ToggleCase,
+    Join,
+    Undo,
+    Repeat,
+    Visual {
+        linewise: bool,
+    },
+    SwapAnchor,
+    Escape,
+}
+
+/// A normal or visual mode command, like `3dw`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Command {
+    count: Option<usize>,
+    kind: CommandKind,
+}
+
+impl Command {
+    fn new(count: Option<usize>, kind: CommandKind) -> Self {
+        Self { count, kind }
+    }
+}
+
+/// The last change, kept so that it can be repeated with `.`
+#[derive(Debug, Clone)]
+struct Change {
+    command: Command,
+    /// The text which was typed in insert mode after the command
+    text: String,
+}
+
+#[derive(Debug, Default)]
+struct Register {
+    text: String,
+    /// Whether whole lines were deleted or yanked, in which case they are put on new lines
+    linewise: bool,
+}
+
+/// A range of the lines for an operator to act on
+struct Range {
+    start: Pos,
+    /// For linewise ranges the last line, otherwise the position just after the range
+    end: Pos,
+    linewise: bool,
+}
+
+/// The state of the vi bindings.
+///
+/// The mode is reset on every command, but the register and what is repeated by `.` are kept
+/// across commands.
+#[derive(Debug)]
+pub struct Vi {
+    pub mode: Mode,
+    /// The keys of the command which is being typed
+    pending: String,
+    /// Where visual mode was started
+    anchor: Pos,
+    register: Register,
+    last_find: Option<Find>,
+    last_change: Option<Change>,
+    /// The change which entered insert mode, which becomes the last change once insert mode is left
+    recording: Option<Change>,
+}
+
+impl Default for Vi {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Insert,
+            pending: String::new(),
+            anchor: (0, 0),
+            register: Register::default(),
+            last_find: None,
+            last_change: None,
+            recording: None,
+        }
+    }
+}
+
+impl Vi {
+    /// Prepares for a new command, which starts in insert mode
+    pub fn reset(&mut self) {
+        self.mode = Mode::Insert;
+        self.pending.clear();
+        self.recording = None;
+    }
+
+    /// The selected range in visual mode, as the first and last selected positions and whether
+    /// whole lines are selected
+    pub fn selection(&self, c: &Cursor, lines: &[String]) -> Option<(Pos, Pos, bool)> {
+        match self.mode {
+            Mode::Visual { linewise } => {
+                let anchor = (min(self.anchor.0, lines.len() - 1), self.anchor.1);
+                let cursor = (c.lineno, c.charno);
+                let (start, end) = if anchor < cursor {
+                    (anchor, cursor)
+                } else {
+                    (cursor, anchor)
+                };
+                Some((start, end, linewise))
+            }
+            _ => None,
+        }
+    }
+
+    /// Decides what to do with a key
+    pub fn key(&mut self, e: KeyEvent) -> Key {
+        if self.mode == Mode::Insert {
+            if e.code == KeyCode::Esc {
+                return Key::Run(Command::new(None, CommandKind::Escape));
+            }
+
+            self.record(e);
+            return Key::Pass;
+        }
+
+        let modified = e
+            .modifiers
+            .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+        let visual = matches!(self.mode, Mode::Visual { .. });
+
+        let kind = match e.code {
+            KeyCode::Char('r') if e.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.pending.clear();
+                return Key::Redo;
+            }
+            KeyCode::Char(chr) if !modified => {
+                self.pending.push(chr);
+                match parse(&self.pending, visual) {
+                    Parse::Incomplete => return Key::Pending,
+                    Parse::Invalid => {
+                        self.pending.clear();
+                        return Key::Pending;
+                    }
+                    Parse::Done(command) => {
+                        self.pending.clear();
+                        if command.kind == CommandKind::Undo {
+                            return Key::Undo;
+                        }
+                        return Key::Run(command);
+                    }
+                }
+            }
+            KeyCode::Esc => CommandKind::Escape,
+            KeyCode::Backspace => CommandKind::Move(Motion::Left),
+            KeyCode::Delete if visual => CommandKind::Selection(Operator::Delete),
+            KeyCode::Delete => CommandKind::Operate(Operator::Delete, Motion::Right),
+            _ => {
+                self.pending.clear();
+                return Key::Pass;
+            }
+        };
+
+        self.pending.clear();
+        Key::Run(Command::new(None, kind))
+    }
+
+    /// Keeps track of what is typed in insert mode, so that it can be repeated with `.`
+    fn record(&mut self, e: KeyEvent) {
+        let modified = e
+            .modifiers
+            .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+
+        if let Some(change) = &mut self.recording {
+            match e.code {
+                KeyCode::Char(chr) if !modified => change.text.push(chr),
+                KeyCode::Tab => change.text.push_str("    "),
+                KeyCode::Enter => change.text.push('\n'),
+                KeyCode::Backspace if !change.text.is_empty() => {
+                    change.text.pop();
+                }
+                // Anything else, such as moving the cursor, cannot be repeated
+                _ => self.recording = None,
+            }
+        }
+    }
+
+    /// Whether the command changes the lines, in which case it should be given to
+    /// [`change`](Vi::change) instead of [`run`](Vi::run)
+    pub fn changes(&self, command: &Command) -> bool {
+        match command.kind {
+            CommandKind::Operate(operator, _)
+            | CommandKind::Lines(operator)
+            | CommandKind::Selection(operator) => operator != Operator::Yank,
+            CommandKind::Insert(Insert::LineBelow)
+            | CommandKind::Insert(Insert::LineAbove)
+            | CommandKind::Put { .. }
+            | CommandKind::Replace(_)
+            | CommandKind::ToggleCase
+            | CommandKind::Join => true,
+            CommandKind::Repeat => self.last_change.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Runs a command which does not change the lines. If the command cannot be handled within
+    /// the lines, such as moving up from the first line, the action which should be run instead
+    /// is given back.
+    pub fn run<L: LangInterface>(
+        &mut self,
+        command: Command,
+        lines: &[String],
+        c: &mut Cursor,
+    ) -> Option<Action> {
+        let pos = (c.lineno, c.charno);
+        let visual = matches!(self.mode, Mode::Visual { .. });
+
+        match command.kind {
+            // Going past the block moves through history, like the arrow keys
+            CommandKind::Move(Motion::Up) if c.lineno == 0 && !visual => {
+                return Some(Action::Up);
+            }
+            CommandKind::Move(Motion::Down) if c.lineno + 1 == lines.len() && !visual => {
+                return Some(Action::Down);
+            }
+            CommandKind::Move(motion) => {
+                if let Some((target, _)) = self.target::<L>(motion, command.count, lines, pos) {
+                    c.lineno = target.0;
+                    c.charno = target.1;
+                }
+            }
+            CommandKind::Operate(operator, motion) => {
+                if let Some(range) = self.range::<L>(operator, motion, command.count, lines, pos) {
+                    self.yank(lines, &range);
+                    if !range.linewise {
+                        c.lineno = range.start.0;
+                        c.charno = range.start.1;
+                    }
+                }
+            }
+            CommandKind::Lines(_) => {
+                let range = lines_range(lines, pos, command.count);
+                self.yank(lines, &range);
+            }
+            CommandKind::Selection(_) => {
+                if let Some(range) = self.selection_range(c, lines) {
+                    self.yank(lines, &range);
+                    c.lineno = range.start.0;
+                    if !range.linewise {
+                        c.charno = range.start.1;
+                    }
+                }
+                self.mode = Mode::Normal;
+            }
+            CommandKind::Insert(insert) => {
+                c.charno = match insert {
+                    Insert::After => min(c.charno + 1, char_count(lines, c.lineno)),
+                    Insert::LineStart => first_non_blank(&lines[c.lineno]),
+                    Insert::LineEnd => char_count(lines, c.lineno),
+                    _ => c.charno,
+                };
+                self.mode = Mode::Insert;
+                self.finish(command);
+            }
+            CommandKind::Visual { linewise } => {
+                self.mode = match self.mode {
+                    Mode::Visual { linewise: selected } if selected == linewise => Mode::Normal,
+                    Mode::Visual { .. } => Mode::Visual { linewise },
+                    _ => {
+                        self.anchor = pos;
+                        Mode::Visual { linewise }
+                    }
+                };
+            }
+            CommandKind::SwapAnchor => {
+                c.lineno = self.anchor.0;
+                c.charno = self.anchor.1;
+                self.anchor = pos;
+            }
+            CommandKind::Escape => {
+                if self.mode == Mode::Insert {
+                    c.charno = c.charno.saturating_sub(1);
+                    if let Some(change) = self.recording.take() {
+                        self.last_change = Some(change);
+                    }
+                }
+                self.mode = Mode::Normal;
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Runs a command which changes the lines
+    pub fn change<L: LangInterface>(
+        &mut self,
+        command: Command,
+        lines: &mut Vec<String>,
+        c: &mut Cursor,
+    ) {
+        let pos = (c.lineno, c.charno);
+        let count = command.count.unwrap_or(1);
+
+        match command.kind {
+            CommandKind::Operate(operator, motion) => {
+                if let Some(range) = self.range::<L>(operator, motion, command.count, lines, pos) {
+                    self.operate::<L>(operator, &range, lines, c);
+                }
+            }
+            CommandKind::Lines(operator) => {
+                let range = lines_range(lines, pos, command.count);
+                self.operate::<L>(operator, &range, lines, c);
+            }
+            CommandKind::Selection(operator) => {
+                let range = self.selection_range(c, lines);
+                self.mode = Mode::Normal;
+                if let Some(range) = range {
+                    self.operate::<L>(operator, &range, lines, c);
+                }
+                // The selection cannot be repeated
+                return;
+            }
+            CommandKind::Insert(insert) => {
+                let lineno = if insert == Insert::LineBelow {
+                    c.lineno + 1
+                } else {
+                    c.lineno
+                };
+                let indent = L::get_indent(&lines[..lineno]);
+
+                lines.insert(lineno, " ".repeat(indent));
+                c.lineno = lineno;
+                c.charno = indent;
+                self.mode = Mode::Insert;
+            }
+            CommandKind::Put { before } => self.put(before, count, lines, c),
+            CommandKind::Replace(chr) => {
+                let line = &mut lines[c.lineno];
+                if c.charno.saturating_add(count) <= line.chars().count() {
+                    remove_chars(line, c.charno, c.charno + count);
+
+                    let byte_i = get_byte_i(line, c.charno);
+                    line.insert_str(byte_i, &chr.to_string().repeat(count));
+                    c.charno += count - 1;
+                }
+            }
+            CommandKind::ToggleCase => {
+                let end = min(c.charno + count, char_count(lines, c.lineno));
+                let range = Range {
+                    start: pos,
+                    end: (c.lineno, end),
+                    linewise: false,
+                };
+                toggle_case(lines, &range);
+                c.charno = end;
+            }
+            CommandKind::Join => {
+                // Joining a single line still joins it with the next
+                for _ in 0..count.saturating_sub(1).max(1) {
+                    if c.lineno + 1 == lines.len() {
+                        break;
+                    }
+
+                    let next = lines.remove(c.lineno + 1);
+                    let next = next.trim_start();
+                    let line = &mut lines[c.lineno];
+
+                    c.charno = line.chars().count();
+                    if !line.is_empty() && !line.ends_with(' ') && !next.is_empty() {
+                        line.push(' ');
+                    }
+                    *line += next;
+                }
+            }
+            CommandKind::Repeat => {
+                if let Some(Change {
+                    command: mut repeated,
+                    text,
+                }) = self.last_change.clone()
+                {
+                    if command.count.is_some() {
+                        repeated.count = command.count;
+                    }
+
+                    if self.changes(&repeated) {
+                        self.change::<L>(repeated, lines, c);
+                    } else {
+                        self.run::<L>(repeated, lines, c);
+                    }
+
+                    if self.mode == Mode::Insert {
+                        insert_typed::<L>(lines, c, &text);
+                        self.mode = Mode::Normal;
+                        c.charno = c.charno.saturating_sub(1);
+                    }
+
+                    self.recording = None;
+                    self.last_change = Some(Change {
+                        command: repeated,
+                        text,
+                    });
+                }
+                return;
+            }
+            _ => return,
+        }
+
+        self.finish(command);
+    }
+
+    /// Saves a command so that it can be repeated. If it entered insert mode, what is typed until
+    /// insert mode is left is saved with it.
+    fn finish(&mut self, command: Command) {
+        let change = Change {
+            command,
+            text: String::new(),
+        };
+
+        if self.mode == Mode::Insert {
+            self.recording = Some(change);
+        } else {
+            self.last_change = Some(change);
+        }
+    }
+
+    /// Where a motion goes from `pos`, and how the text passed over is used by operators
+    fn target<L: LangInterface>(
+        &mut self,
+        motion: Motion,
+        count: Option<usize>,
+        lines: &[String],
+        pos: Pos,
+    ) -> Option<(Pos, Kind)> {
+        let n = count.unwrap_or(1);
+        let (lineno, charno) = pos;
+        let last = lines.len() - 1;
+
+        let words = |step: fn(&[char], usize, bool) -> usize, big: bool| {
+            let chars = flatten(lines);
+            let i = (0..n).fold(to_flat(lines, pos), |i, _| step(&chars, i, big));
+            from_flat(lines, i)
+        };
+
+        Some(match motion {
+            Motion::Left => ((lineno, charno.saturating_sub(n)), Kind::Exclusive),
+            Motion::Right => (
+                (lineno, min(charno + n, char_count(lines, lineno))),
+                Kind::Exclusive,
+            ),
+            Motion::Up => ((lineno.saturating_sub(n), charno), Kind::Linewise),
+            Motion::Down => ((min(lineno + n, last), charno), Kind::Linewise),
+            Motion::WordStart(big) => (words(word_start::<L>, big), Kind::Exclusive),
+            Motion::WordBack(big) => (words(word_back::<L>, big), Kind::Exclusive),
+            Motion::WordEnd(big) => (words(word_end::<L>, big), Kind::Inclusive),
+            Motion::LineStart => ((lineno, 0), Kind::Exclusive),
+            Motion::FirstNonBlank => ((lineno, first_non_blank(&lines[lineno])), Kind::Exclusive),
+            Motion::LineEnd => {
+                let lineno = min(lineno + n - 1, last);
+                let end = char_count(lines, lineno).saturating_sub(1);
+                ((lineno, end), Kind::Inclusive)
+            }
+            Motion::Find(find) => {
+                self.last_find = Some(find);
+                return find_char(&lines[lineno], pos, find, n, false);
+            }
+            Motion::RepeatFind(reverse) => {
+                let mut find = self.last_find?;
+                find.forward ^= reverse;
+                return find_char(&lines[lineno], pos, find, n, true);
+            }
+            Motion::FirstLine | Motion::LastLine => {
+                let lineno = match count {
+                    Some(n) => min(n, lines.len()) - 1,
+                    None if motion == Motion::FirstLine => 0,
+                    None => last,
+                };
+                ((lineno, first_non_blank(&lines[lineno])), Kind::Linewise)
+            }
+        })
+    }
+
+    /// The range of text between `pos` and where a motion goes
+    fn range<L: LangInterface>(
+        &mut self,
+        operator: Operator,
+        motion: Motion,
+        count: Option<usize>,
+        lines: &[String],
+        pos: Pos,
+    ) -> Option<Range> {
+        // Like vim, `cw` on a word only changes up to the end of the word
+        let on_word = lines[pos.0]
+            .chars()
+            .nth(pos.1)
+            .map(|chr| !chr.is_whitespace())
+            .unwrap_or(false);
+        let motion = match motion {
+            Motion::WordStart(big) if operator == Operator::Change && on_word => {
+                Motion::WordEnd(big)
+            }
+            motion => motion,
+        };
+
+        // `cw` on the last character of a word only changes that character
+        let (target, kind) = match motion {
+            Motion::WordEnd(_) if operator == Operator::Change && on_word && count.is_none() => {
+                let chars = flatten(lines);
+                let i = to_flat(lines, pos);
+                let big = motion == Motion::WordEnd(true);
+                let same = |j: usize| {
+                    j < chars.len() && class::<L>(chars[j], big) == class::<L>(chars[i], big)
+                };
+                if same(i + 1) {
+                    self.target::<L>(motion, count, lines, pos)?
+                } else {
+                    (pos, Kind::Inclusive)
+                }
+            }
+            motion => self.target::<L>(motion, count, lines, pos)?,
+        };
+
+        let (start, mut end) = if target < pos {
+            (target, pos)
+        } else {
+            (pos, target)
+        };
+
+        Some(match kind {
+            Kind::Linewise => Range {
+                start: (start.0, 0),
+                end: (end.0, 0),
+                linewise: true,
+            },
+            Kind::Inclusive => {
+                end.1 = min(end.1 + 1, char_count(lines, end.0));
+                Range {
+                    start,
+                    end,
+                    linewise: false,
+                }
+            }
+            Kind::Exclusive => {
+                // A motion which ends at the start of a later line, such as `dw` on the last word
+                // of a line, does not include the line break
+                if end.0 > start.0 && end.1 == 0 {
+                    end = (end.0 - 1, char_count(lines, end.0 - 1));
+                }
+                Range {
+                    start,
+                    end,
+                    linewise: false,
+                }
+            }
+        })
+    }
+
+    /// The range of text which is selected in visual mode
+    fn selection_range(&self, c: &Cursor, lines: &[String]) -> Option<Range> {
+        let (start, end, linewise) = self.selection(c, lines)?;
+        let end = if linewise {
+            end
+        } else {
+            (end.0, min(end.1 + 1, char_count(lines, end.0)))
+        };
+
+        Some(Range {
+            start,
+            end,
+            linewise,
+        })
+    }
+
+    fn yank(&mut self, lines: &[String], range: &Range) {
+        self.register = Register {
+            text: remove(&mut lines.to_vec(), range),
+            linewise: range.linewise,
+        };
+    }
+
+    fn operate<L: LangInterface>(
+        &mut self,
+        operator: Operator,
+        range: &Range,
+        lines: &mut Vec<String>,
+        c: &mut Cursor,
+    ) {
+        match operator {
+            Operator::Yank => {
+                self.yank(lines, range);
+                return;
+            }
+            Operator::ToggleCase => toggle_case(lines, range),
+            Operator::Delete | Operator::Change => {
+                self.register = Register {
+                    text: remove(lines, range),
+                    linewise: range.linewise,
+                };
+            }
+        }
+
+        c.lineno = range.start.0;
+        c.charno = range.start.1;
+
+        if operator == Operator::Change {
+            // Changed lines are replaced with a single line to type into
+            if range.linewise {
+                if lines.len() == 1 && lines[0].is_empty() {
+                    lines.clear();
+                }
+
+                let indent = L::get_indent(&lines[..c.lineno]);
+                lines.insert(c.lineno, " ".repeat(indent));
+                c.charno = indent;
+            }
+            self.mode = Mode::Insert;
+        } else if range.linewise {
+            c.lineno = min(c.lineno, lines.len() - 1);
+            c.charno = first_non_blank(&lines[c.lineno]);
+        }
+    }
+
+    fn put(&mut self, before: bool, count: usize, lines: &mut Vec<String>, c: &mut Cursor) {
+        if self.register.text.is_empty() && !self.register.linewise {
+            return;
+        }
+
+        if self.register.linewise {
+            let lineno = if before { c.lineno } else { c.lineno + 1 };
+            let text = vec![self.register.text.as_str(); count].join("\n");
+            for (i, line) in text.split('\n').enumerate() {
+                lines.insert(lineno + i, line.to_owned());
+            }
+
+            c.lineno = lineno;
+            c.charno = first_non_blank(&lines[lineno]);
+        } else {
+            let len = char_count(lines, c.lineno);
+            let pos = if before || len == 0 {
+                (c.lineno, c.charno)
+            } else {
+                (c.lineno, min(c.charno + 1, len))
+            };
+            let end = insert(lines, pos, &self.register.text.repeat(count));
+
+            // The cursor is left on the last character which was put
+            let end = from_flat(lines, to_flat(lines, end).saturating_sub(1));
+            c.lineno = end.0;
+            c.charno = end.1;
+        }
+    }
+}
+
+enum Parse<T> {
+    Incomplete,
+    Invalid,
+    Done(T),
+}
+
+/// The largest count a command can have. Larger counts are lowered to it, so that typing a long
+/// count does not overflow, or make the editor hang putting text.
+const MAX_COUNT: usize = 9999;
+
+/// Splits a count from the start of the keys. A count cannot start with `0`, since that is a motion.
+fn split_count(keys: &str) -> (Option<usize>, &str) {
+    let digits = keys
+        .char_indices()
+        .find(|&(i, chr)| !chr.is_ascii_digit() || (i == 0 && chr == '0'))
+        .map(|(i, _)| i)
+        .unwrap_or_else(|| keys.len());
+
+    let count = match &keys[..digits] {
+        "" => None,
+        // A count too long to parse is lowered as well
+        digits => Some(digits.parse().map_or(MAX_COUNT, |n| min(n, MAX_COUNT))),
+    };
+    (count, &keys[digits..])
+}
+
+/// Parses the keys of a normal or visual mode command
+fn parse(keys: &str, visual: bool) -> Parse<Command> {
+    let (count, keys) = split_count(keys);
+    let mut chars = keys.chars();
+    let chr = match chars.next() {
+        Some(chr) => chr,
+        None => return Parse::Incomplete,
+    };
+    let rest = chars.as_str();
+
+    let operator = match chr {
+        'd' => Some(Operator::Delete),
+        'c' => Some(Operator::Change),
+        'y' => Some(Operator::Yank),
+        _ => None,
+    };
+
+    let kind = match (chr, operator) {
+        ('d', _) | ('x', _) if visual => CommandKind::Selection(Operator::Delete),
+        ('c', _) | ('s', _) if visual => CommandKind::Selection(Operator::Change),
+        ('y', _) if visual => CommandKind::Selection(Operator::Yank),
+        ('~', _) if visual => CommandKind::Selection(Operator::ToggleCase),
+        ('o', _) if visual => CommandKind::SwapAnchor,
+        (_, Some(operator)) => {
+            if rest.starts_with(chr) {
+                CommandKind::Lines(operator)
+            } else {
+                let (motion_count, rest) = split_count(rest);
+                let count = match (count, motion_count) {
+                    (None, None) => None,
+                    (count, motion_count) => Some(min(
+                        count.unwrap_or(1).saturating_mul(motion_count.unwrap_or(1)),
+                        MAX_COUNT,
+                    )),
+                };
+
+                return match parse_motion(rest) {
+                    Parse::Done(motion) => {
+                        Parse::Done(Command::new(count, CommandKind::Operate(operator, motion)))
+                    }
+                    Parse::Incomplete => Parse::Incomplete,
+                    Parse::Invalid => Parse::Invalid,
+                };
+            }
+        }
+        ('x', _) => CommandKind::Operate(Operator::Delete, Motion::Right),
+        ('X', _) => CommandKind::Operate(Operator::Delete, Motion::Left),
+        ('D', _) => CommandKind::Operate(Operator::Delete, Motion::LineEnd),
+        ('C', _) => CommandKind::Operate(Operator::Change, Motion::LineEnd),
+        ('s', _) => CommandKind::Operate(Operator::Change, Motion::Right),
+        ('S', _) => CommandKind::Lines(Operator::Change),
+        ('Y', _) => CommandKind::Lines(Operator::Yank),
+        ('i', _) => CommandKind::Insert(Insert::Before),
+        ('a', _) => CommandKind::Insert(Insert::After),
+        ('I', _) => CommandKind::Insert(Insert::LineStart),
+        ('A', _) => CommandKind::Insert(Insert::LineEnd),
+        ('o', _) => CommandKind::Insert(Insert::LineBelow),
+        ('O', _) => CommandKind::Insert(Insert::LineAbove),
+        ('p', _) => CommandKind::Put { before: false },
+        ('P', _) => CommandKind::Put { before: true },
+        ('r', _) => match rest.chars().next() {
+            Some(chr) => CommandKind::Replace(chr),
+            None => return Parse::Incomplete,
+        },
+        ('~', _) => CommandKind::ToggleCase,
+        ('J', _) => CommandKind::Join,
+        ('u', _) => CommandKind::Undo,
+        ('.', _) => CommandKind::Repeat,
+        ('v', _) => CommandKind::Visual { linewise: false },
+        ('V', _) => CommandKind::Visual { linewise: true },
+        _ => {
+            return match parse_motion(keys) {
+                Parse::Done(motion) => Parse::Done(Command::new(count, CommandKind::Move(motion))),
+                Parse::Incomplete => Parse::Incomplete,
+                Parse::Invalid => Parse::Invalid,
+            }
+        }
+    };
+
+    Parse::Done(Command::new(count, kind))
+}
+
+/// Parses the keys of a motion
+fn parse_motion(keys: &str) -> Parse<Motion> {
+    let mut chars = keys.chars();
+    let chr = match chars.next() {
+        Some(chr) => chr,
+        None => return Parse::Incomplete,
+    };
+    let next = chars.next();
+
+    let find = |forward, till| match next {
+        Some(chr) => Parse::Done(Motion::Find(Find { chr, forward, till })),
+        None => Parse::Incomplete,
+    };
+
+    Parse::Done(match chr {
+        'h' => Motion::Left,
+        'l' | ' ' => Motion::Right,
+        'k' => Motion::Up,
+        'j' => Motion::Down,
+        'w' => Motion::WordStart(false),
+        'W' => Motion::WordStart(true),
+        'b' => Motion::WordBack(false),
+        'B' => Motion::WordBack(true),
+        'e' => Motion::WordEnd(false),
+        'E' => Motion::WordEnd(true),
+        '0' => Motion::LineStart,
+        '^' => Motion::FirstNonBlank,
+        '$' => Motion::LineEnd,
+        'f' => return find(true, false),
+        't' => return find(true, true),
+        'F' => return find(false, false),
+        'T' => return find(false, true),
+        ';' => Motion::RepeatFind(false),
+        ',' => Motion::RepeatFind(true),
+        'g' => match next {
+            Some('g') => Motion::FirstLine,
+            Some(_) => return Parse::Invalid,
+            None => return Parse::Incomplete,
+        },
+        'G' => Motion::LastLine,
+        _ => return Parse::Invalid,
+    })
+}
+
+fn char_count(lines: &[String], lineno: usize) -> usize {
+    lines[lineno].chars().count()
+}
+
+fn first_non_blank(line: &str) -> usize {
+    line.chars().take_while(|chr| chr.is_whitespace()).count()
+}
+
+/// Finds the `n`th occurrence of a character in the line, from `pos`. If `repeat` is true and the
+/// cursor is stopped just before the character, it is skipped so that the cursor moves on.
+fn find_char(line: &str, pos: Pos, find: Find, n: usize, repeat: bool) -> Option<(Pos, Kind)> {
+    let chars: Vec<_> = line.chars().collect();
+    let skip = (repeat && find.till) as usize;
+
+    if find.forward {
+        let mut i = pos.1 + skip;
+        for _ in 0..n {
+            i = (i + 1..chars.len()).find(|&i| chars[i] == find.chr)?;
+        }
+
+        let i = if find.till { i - 1 } else { i };
+        Some(((pos.0, i), Kind::Inclusive))
+    } else {
+        let mut i = pos.1.saturating_sub(skip);
+        for _ in 0..n {
+            i = (0..i).rev().find(|&i| chars[i] == find.chr)?;
+        }
+
+        let i = if find.till { i + 1 } else { i };
+        Some(((pos.0, i), Kind::Exclusive))
+    }
+}
+
+/// The lines the lines operator acts on, such as `3dd`
+fn lines_range(lines: &[String], pos: Pos, count: Option<usize>) -> Range {
+    let last = min(
+        pos.0.saturating_add(count.unwrap_or(1) - 1),
+        lines.len() - 1,
+    );
+    Range {
+        start: (pos.0, 0),
+        end: (last, 0),
+        linewise: true,
+    }
+}
+
+/// The lines as a single list of characters, with `'\n'` between lines, so that words can be
+/// found across lines
+fn flatten(lines: &[String]) -> Vec<char> {
+    lines.join("\n").chars().collect()
+}
+
+/// The index of a position in the [`flatten`]ed lines
+fn to_flat(lines: &[String], pos: Pos) -> usize {
+    lines[..pos.0]
+        .iter()
+        .map(|line| line.chars().count() + 1)
+        .sum::<usize>()
+        + pos.1
+}
+
+/// The position of an index in the [`flatten`]ed lines
+fn from_flat(lines: &[String], mut i: usize) -> Pos {
+    for (lineno, line) in lines.iter().enumerate() {
+        let len = line.chars().count();
+        if i <= len {
+            return (lineno, i);
+        }
+        i -= len + 1;
+    }
+
+    let last = lines.len() - 1;
+    (last, char_count(lines, last))
+}
+
+/// Whether index `i` of the flattened lines is the start of an empty line
+fn empty_line(chars: &[char], i: usize) -> bool {
+    (i == chars.len() || chars[i] == '\n') && (i == 0 || chars[i - 1] == '\n')
+}
+
+/// Characters of the same class are part of the same word. Whitespace is 0, word characters are
+/// 1, and other characters are 2. If `big` is true, every character except whitespace is part of
+/// the same word.
+fn class<L: LangInterface>(chr: char, big: bool) -> u8 {
+    if chr.is_whitespace() {
+        0
+    } else if big || L::is_word_char(chr) {
+        1
+    } else {
+        2
+    }
+}
+
+/// The start of the next word, like `w`
+fn word_start<L: LangInterface>(chars: &[char], mut i: usize, big: bool) -> usize {
+    let n = chars.len();
+    if i >= n {
+        return n;
+    }
+
+    let start = class::<L>(chars[i], big);
+    if start != 0 {
+        while i < n && class::<L>(chars[i], big) == start {
+            i += 1;
+        }
+    }
+
+    // An empty line is also a word
+    while i < n && chars[i].is_whitespace() {
+        i += 1;
+        if empty_line(chars, i) {
+            break;
+        }
+    }
+
+    i
+}
+
+/// The start of the previous word, like `b`
+fn word_back<L: LangInterface>(chars: &[char], mut i: usize, big: bool) -> usize {
+    if i == 0 {
+        return 0;
+    }
+
+    i -= 1;
+    while i > 0 && (i == chars.len() || chars[i].is_whitespace()) {
+        if empty_line(chars, i) {
+            return i;
+        }
+        i -= 1;
+    }
+
+    if chars[i].is_whitespace() {
+        return i;
+    }
+
+    let start = class::<L>(chars[i], big);
+    while i > 0 && class::<L>(chars[i - 1], big) == start {
+        i -= 1;
+    }
+
+    i
+}
+
+/// The end of the current or next word, like `e`
+fn word_end<L: LangInterface>(chars: &[char], mut i: usize, big: bool) -> usize {
+    let n = chars.len();
+
+    i += 1;
+    while i < n && chars[i].is_whitespace() {
+        i += 1;
+    }
+    if i >= n {
+        return n.saturating_sub(1);
+    }
+
+    let start = class::<L>(chars[i], big);
+    while i + 1 < n && class::<L>(chars[i + 1], big) == start {
+        i += 1;
+    }
+
+    i
+}
+
+/// Removes the text in a range, giving back what was removed
+fn remove(lines: &mut Vec<String>, range: &Range) -> String {
+    let (start, end) = (range.start, range.end);
+
+    if range.linewise {
+        let removed: Vec<_> = lines.drain(start.0..=end.0).collect();
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+        return removed.join("\n");
+    }
+
+    if start.0 == end.0 {
+        return remove_chars(&mut lines[start.0], start.1, end.1);
+    }
+
+    let len = char_count(lines, start.0);
+    let mut text = remove_chars(&mut lines[start.0], start.1, len);
+    for line in lines.drain(start.0 + 1..end.0) {
+        text.push('\n');
+        text += &line;
+    }
+
+    // The last line of the range is now just after the first
+    let last = lines.remove(start.0 + 1);
+    let byte_i = get_byte_i(&last, end.1);
+    text.push('\n');
+    text += &last[..byte_i];
+    lines[start.0] += &last[byte_i..];
+
+    text
+}
+
+/// Inserts text which can have new lines at `pos`, giving back the position just after it
+fn insert(lines: &mut Vec<String>, pos: Pos, text: &str) -> Pos {
+    let byte_i = get_byte_i(&lines[pos.0], pos.1);
+    let tail = lines[pos.0].split_off(byte_i);
+
+    let mut lineno = pos.0;
+    let mut parts = text.split('\n');
+    lines[lineno] += parts.next().unwrap_or("");
+    for part in parts {
+        lineno += 1;
+        lines.insert(lineno, part.to_owned());
+    }
+
+    let charno = char_count(lines, lineno);
+    lines[lineno] += &tail;
+    (lineno, charno)
+}
+
+/// Inserts text which was typed in insert mode, where a new line is added for every `'\n'` like
+/// pressing enter does
+fn insert_typed<L: LangInterface>(lines: &mut Vec<String>, c: &mut Cursor, text: &str) {
+    for chr in text.chars() {
+        if chr == '\n' {
+            let indent = L::get_indent(&lines[..=c.lineno]);
+            c.lineno += 1;
+            c.charno = indent;
+            lines.insert(c.lineno, " ".repeat(indent));
+        } else {
+            let byte_i = get_byte_i(&lines[c.lineno], c.charno);
+            lines[c.lineno].insert(byte_i, chr);
+            c.charno += 1;
+        }
+    }
+}
+
+fn toggle_case(lines: &mut [String], range: &Range) {
+    let selected = lines
+        .iter_mut()
+        .enumerate()
+        .take(range.end.0 + 1)
+        .skip(range.start.0);
+
+    for (lineno, line) in selected {
+        let len = line.chars().count();
+        let from = if range.linewise || lineno > range.start.0 {
+            0
+        } else {
+            range.start.1
+        };
+        let to = if range.linewise || lineno < range.end.0 {
+            len
+        } else {
+            range.end.1
+        };
+
+        *line = line
+            .chars()
+            .enumerate()
+            .map(|(i, chr)| {
+                if i < from || i >= to {
+                    chr.to_string()
+                } else if chr.is_uppercase() {
+                    chr.to_lowercase().to_string()
+                } else {
+                    chr.to_uppercase().to_string()
+                }
+            })
+            .collect();
+    }
+}
@@ -0,0 +1,93 @@
+use crate::lang::{DefaultLangInterface, LangInterface};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// An iterator over the commands in some source, such as a startup file.
+///
+/// The source is split into commands the same way the [Repl](crate::Repl) would if it was typed
+/// in: lines are added to a command until [`LangInterface::get_indent`] says the block is
+/// complete. Empty lines between commands are skipped.
+///
+/// ```
+/// use shelp::Commands;
+///
+/// let src = "let a = 2\n\nlet b = 3\n";
+/// let commands: Vec<_> = Commands::newd(src.as_bytes()).collect::<Result<_, _>>().unwrap();
+///
+/// assert_eq!(commands, ["let a = 2", "let b = 3"]);
+/// ```
+pub struct Commands<L: LangInterface = DefaultLangInterface, R: BufRead = BufReader<File>> {
+    reader: R,
+    _lang_interface: PhantomData<L>,
+}
+
+impl<R: BufRead> Commands<DefaultLangInterface, R> {
+    /// Split `reader` into commands with default language interface.
+    pub fn newd(reader: R) -> Self {
+        Self::new(reader)
+    }
+}
+
+impl<L: LangInterface, R: BufRead> Commands<L, R> {
+    /// Split `reader` into commands with specified language interface.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            _lang_interface: PhantomData,
+        }
+    }
+}
+
+impl<L: LangInterface> Commands<L> {
+    /// Split the file at `path` into commands with specified language interface.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<L: LangInterface, R: BufRead> Iterator for Commands<L, R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let reader = &mut self.reader;
+        read_block::<L, _>(|buf| reader.read_line(buf))
+            .map(|lines| lines.map(|lines| lines.join("\n")))
+            .transpose()
+    }
+}
+
+/// Reads lines with `read_line` until they form a complete block according to
+/// [`LangInterface::get_indent`]. Empty lines before the block are skipped.
+///
+/// `None` is returned if there was nothing left to read, and if the input ends in the middle of a
+/// block, the unfinished block is given.
+pub(crate) fn read_block<L, F>(mut read_line: F) -> io::Result<Option<Vec<String>>>
+where
+    L: LangInterface,
+    F: FnMut(&mut String) -> io::Result<usize>,
+{
+    let mut lines = Vec::new();
+
+    loop {
+        let mut line = String::new();
+        if read_line(&mut line)? == 0 {
+            break;
+        }
+
+        let line = line.trim_end_matches(&['\n', '\r'][..]);
+
+        if lines.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+
+        lines.push(line.to_owned());
+
+        if L::get_indent(&lines) == 0 {
+            break;
+        }
+    }
+
+    Ok(if lines.is_empty() { None } else { Some(lines) })
+}
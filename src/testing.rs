@@ -0,0 +1,502 @@
+//! Utilities to run a [`Repl`] without a terminal, so that it can be tested.
+//!
+//! [`ScriptedInput`] is an [`InputSource`] which gives a predefined list of events, and
+//! [`VirtualTerminal`] is an output which interprets the escape sequences written to it onto a
+//! screen buffer of fixed size. Both of them are cheap handles to shared state, so a clone can be
+//! given to the `Repl` while the original is kept to feed more events or inspect the screen.
+//!
+//! [`Harness`] ties the two together:
+//! ```
+//! use shelp::{testing::Harness, Signal};
+//!
+//! let mut harness = Harness::newd(20, 5);
+//! let signal = harness.run("1 + 2\n").unwrap();
+//!
+//! assert_eq!(signal, Signal::Command("1 + 2".to_owned()));
+//! assert_eq!(harness.terminal.row(0), "> 1 + 2");
+//! assert_eq!(harness.terminal.cursor(), (0, 1));
+//! ```
+use crate::input::InputSource;
+use crate::lang::{DefaultLangInterface, LangInterface};
+use crate::{Repl, Signal};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Color, Colored};
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+
+/// An [`InputSource`] which gives back events that have been pushed to it.
+///
+/// When there are no more events left, an error is returned instead of blocking forever.
+#[derive(Debug, Default, Clone)]
+pub struct ScriptedInput {
+    events: Rc<RefCell<VecDeque<Event>>>,
+}
+
+impl ScriptedInput {
+    /// Create an input with no events
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue an event
+    pub fn push(&self, event: Event) {
+        self.events.borrow_mut().push_back(event);
+    }
+
+    /// Queue a key press without any modifiers
+    pub fn key(&self, code: KeyCode) {
+        self.key_with(code, KeyModifiers::NONE);
+    }
+
+    /// Queue a key press with the given modifiers
+    pub fn key_with(&self, code: KeyCode, modifiers: KeyModifiers) {
+        self.push(Event::Key(KeyEvent { code, modifiers }));
+    }
+
+    /// Queue a key press for every character in `s`, where a `'\n'` is given as [`KeyCode::Enter`]
+    pub fn type_str(&self, s: &str) {
+        for chr in s.chars() {
+            match chr {
+                '\n' => self.key(KeyCode::Enter),
+                '\t' => self.key(KeyCode::Tab),
+                chr => self.key(KeyCode::Char(chr)),
+            }
+        }
+    }
+
+    /// The number of events which have not yet been read
+    pub fn remaining(&self) -> usize {
+        self.events.borrow().len()
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn read(&mut self) -> crate::Result<Event> {
+        self.events.borrow_mut().pop_front().ok_or_else(|| {
+            crossterm::ErrorKind::IoError(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "No more scripted events",
+            ))
+        })
+    }
+}
+
+/// A single character on the [`VirtualTerminal`] screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            ch: ' ',
+            fg: Color::Reset,
+            bg: Color::Reset,
+        }
+    }
+}
+
+/// A simulated terminal screen, which can be written to.
+///
+/// It understands the escape sequences which crossterm emits for cursor movement, clearing,
+/// scrolling and colours. Like a terminal in raw mode, `'\n'` only moves the cursor down and `'\r'`
+/// is needed to go back to the first column. When something is written past the bottom of the
+/// screen, it is scrolled up.
+#[derive(Debug, Clone)]
+pub struct VirtualTerminal {
+    screen: Rc<RefCell<Screen>>,
+}
+
+impl VirtualTerminal {
+    /// Create a blank screen with `width` columns and `height` rows
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            screen: Rc::new(RefCell::new(Screen::new(width, height))),
+        }
+    }
+
+    /// The size of the screen as `(columns, rows)`
+    pub fn size(&self) -> (u16, u16) {
+        let screen = self.screen.borrow();
+        (screen.width as u16, screen.height as u16)
+    }
+
+    /// The position of the cursor as `(column, row)`
+    pub fn cursor(&self) -> (u16, u16) {
+        let screen = self.screen.borrow();
+        (min(screen.x, screen.width - 1) as u16, screen.y as u16)
+    }
+
+    /// The cell at the given position
+    pub fn cell(&self, column: u16, row: u16) -> Cell {
+        self.screen.borrow().cells[row as usize][column as usize]
+    }
+
+    /// The text on the given row, without trailing whitespace
+    pub fn row(&self, row: u16) -> String {
+        let screen = self.screen.borrow();
+        let line: String = screen.cells[row as usize].iter().map(|c| c.ch).collect();
+        line.trim_end().to_owned()
+    }
+
+    /// The text on every row, without trailing whitespace
+    pub fn rows(&self) -> Vec<String> {
+        (0..self.size().1).map(|row| self.row(row)).collect()
+    }
+
+    /// The text on the screen, with trailing empty rows removed
+    pub fn contents(&self) -> String {
+        let mut rows = self.rows();
+        while rows.last().map(|row| row.is_empty()).unwrap_or(false) {
+            rows.pop();
+        }
+        rows.join("\n")
+    }
+}
+
+impl io::Write for VirtualTerminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut screen = self.screen.borrow_mut();
+        screen.pending.extend_from_slice(buf);
+        screen.process();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Screen {
+    width: usize,
+    height: usize,
+    cells: Vec<Vec<Cell>>,
+    /// The cursor column. It can be equal to `width` after writing to the last column, in which
+    /// case the next character wraps to the next row.
+    x: usize,
+    y: usize,
+    fg: Color,
+    bg: Color,
+    saved: (usize, usize),
+    /// Bytes which have been written but not yet processed, since they are an incomplete escape
+    /// sequence or utf-8 character.
+    pending: Vec<u8>,
+}
+
+impl Screen {
+    fn new(width: u16, height: u16) -> Self {
+        let width = width.max(1) as usize;
+        let height = height.max(1) as usize;
+        Self {
+            width,
+            height,
+            cells: vec![vec![Cell::default(); width]; height],
+            x: 0,
+            y: 0,
+            fg: Color::Reset,
+            bg: Color::Reset,
+            saved: (0, 0),
+            pending: Vec::new(),
+        }
+    }
+
+    fn process(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        let mut i = 0;
+
+        while i < pending.len() {
+            let consumed = match pending[i] {
+                0x1B => self.escape(&pending[i..]),
+                b'\n' => {
+                    self.line_feed();
+                    Some(1)
+                }
+                b'\r' => {
+                    self.x = 0;
+                    Some(1)
+                }
+                0x08 => {
+                    self.x = self.x.saturating_sub(1);
+                    Some(1)
+                }
+                byte if byte < 0x20 => Some(1),
+                _ => self.print(&pending[i..]),
+            };
+
+            match consumed {
+                Some(n) => i += n,
+                None => break,
+            }
+        }
+
+        self.pending = pending[i..].to_vec();
+    }
+
+    /// Handles an escape sequence at the start of `bytes`, returning the number of bytes used or
+    /// `None` if the sequence is incomplete.
+    fn escape(&mut self, bytes: &[u8]) -> Option<usize> {
+        match bytes.get(1)? {
+            b'[' => {
+                let end = bytes[2..].iter().position(|b| (0x40..=0x7E).contains(b))? + 2;
+                let params = String::from_utf8_lossy(&bytes[2..end]);
+                self.csi(&params, bytes[end]);
+                Some(end + 1)
+            }
+            b'7' => {
+                self.saved = (self.x, self.y);
+                Some(2)
+            }
+            b'8' => {
+                let (x, y) = self.saved;
+                self.x = x;
+                self.y = y;
+                Some(2)
+            }
+            _ => Some(2),
+        }
+    }
+
+    fn csi(&mut self, params: &str, action: u8) {
+        // Private sequences such as showing and hiding the cursor do not affect the screen
+        if params.starts_with('?') {
+            return;
+        }
+
+        let args: Vec<usize> = params
+            .split(';')
+            .map(|arg| arg.parse().unwrap_or(0))
+            .collect();
+        let arg = |i: usize, default: usize| match args.get(i) {
+            Some(&0) | None => default,
+            Some(&n) => n,
+        };
+
+        match action {
+            b'A' => self.y = self.y.saturating_sub(arg(0, 1)),
+            b'B' => self.y = min(self.y + arg(0, 1), self.height - 1),
+            b'C' => self.x = min(self.x + arg(0, 1), self.width - 1),
+            b'D' => self.x = min(self.x, self.width - 1).saturating_sub(arg(0, 1)),
+            b'E' => {
+                self.y = min(self.y + arg(0, 1), self.height - 1);
+                self.x = 0;
+            }
+            b'F' => {
+                self.y = self.y.saturating_sub(arg(0, 1));
+                self.x = 0;
+            }
+            b'G' => self.x = min(arg(0, 1), self.width) - 1,
+            b'H' | b'f' => {
+                self.y = min(arg(0, 1), self.height) - 1;
+                self.x = min(arg(1, 1), self.width) - 1;
+            }
+            b'J' => {
+                let y = self.y;
+                match args[0] {
+                    0 => {
+                        self.clear_line(y, min(self.x, self.width), self.width);
+                        for y in (y + 1)..self.height {
+                            self.clear_line(y, 0, self.width);
+                        }
+                    }
+                    1 => {
+                        for y in 0..y {
+                            self.clear_line(y, 0, self.width);
+                        }
+                        self.clear_line(y, 0, min(self.x + 1, self.width));
+                    }
+                    _ => {
+                        for y in 0..self.height {
+                            self.clear_line(y, 0, self.width);
+                        }
+                    }
+                }
+            }
+            b'K' => {
+                let y = self.y;
+                match args[0] {
+                    0 => self.clear_line(y, min(self.x, self.width), self.width),
+                    1 => self.clear_line(y, 0, min(self.x + 1, self.width)),
+                    _ => self.clear_line(y, 0, self.width),
+                }
+            }
+            b'S' => {
+                for _ in 0..arg(0, 1) {
+                    self.scroll_up();
+                }
+            }
+            b'T' => {
+                for _ in 0..arg(0, 1) {
+                    self.cells.pop();
+                    self.cells.insert(0, vec![Cell::default(); self.width]);
+                }
+            }
+            b'm' => self.sgr(params),
+            _ => {}
+        }
+    }
+
+    fn sgr(&mut self, params: &str) {
+        match Colored::parse_ansi(params) {
+            Some(Colored::ForegroundColor(color)) => self.fg = color,
+            Some(Colored::BackgroundColor(color)) => self.bg = color,
+            None => {
+                for param in params.split(';') {
+                    match param.parse().unwrap_or(0) {
+                        0 => {
+                            self.fg = Color::Reset;
+                            self.bg = Color::Reset;
+                        }
+                        n @ 30..=37 => self.fg = basic_color(n - 30, false),
+                        n @ 90..=97 => self.fg = basic_color(n - 90, true),
+                        39 => self.fg = Color::Reset,
+                        n @ 40..=47 => self.bg = basic_color(n - 40, false),
+                        n @ 100..=107 => self.bg = basic_color(n - 100, true),
+                        49 => self.bg = Color::Reset,
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn print(&mut self, bytes: &[u8]) -> Option<usize> {
+        let len = match bytes[0] {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1,
+        };
+        if bytes.len() < len {
+            return None;
+        }
+
+        let ch = std::str::from_utf8(&bytes[..len])
+            .ok()
+            .and_then(|s| s.chars().next())
+            .unwrap_or(char::REPLACEMENT_CHARACTER);
+
+        if self.x >= self.width {
+            self.x = 0;
+            self.line_feed();
+        }
+
+        self.cells[self.y][self.x] = Cell {
+            ch,
+            fg: self.fg,
+            bg: self.bg,
+        };
+        self.x += 1;
+
+        Some(len)
+    }
+
+    fn line_feed(&mut self) {
+        if self.y + 1 == self.height {
+            self.scroll_up();
+        } else {
+            self.y += 1;
+        }
+    }
+
+    fn scroll_up(&mut self) {
+        self.cells.remove(0);
+        self.cells.push(vec![Cell::default(); self.width]);
+    }
+
+    fn clear_line(&mut self, y: usize, from: usize, to: usize) {
+        for cell in &mut self.cells[y][from..to] {
+            *cell = Cell::default();
+        }
+    }
+}
+
+fn basic_color(n: usize, bright: bool) -> Color {
+    match (n, bright) {
+        (0, false) => Color::Black,
+        (1, false) => Color::DarkRed,
+        (2, false) => Color::DarkGreen,
+        (3, false) => Color::DarkYellow,
+        (4, false) => Color::DarkBlue,
+        (5, false) => Color::DarkMagenta,
+        (6, false) => Color::DarkCyan,
+        (7, false) => Color::Grey,
+        (0, true) => Color::DarkGrey,
+        (1, true) => Color::Red,
+        (2, true) => Color::Green,
+        (3, true) => Color::Yellow,
+        (4, true) => Color::Blue,
+        (5, true) => Color::Magenta,
+        (6, true) => Color::Cyan,
+        _ => Color::White,
+    }
+}
+
+/// A [`Repl`] which reads from a [`ScriptedInput`] and writes to a [`VirtualTerminal`].
+pub type TestRepl<L = DefaultLangInterface> = Repl<L, ScriptedInput, VirtualTerminal>;
+
+/// A [`Repl`] connected to a [`ScriptedInput`] and [`VirtualTerminal`], with handles to both so
+/// that events can be fed and the screen inspected.
+pub struct Harness<L: LangInterface = DefaultLangInterface> {
+    pub repl: TestRepl<L>,
+    pub input: ScriptedInput,
+    pub terminal: VirtualTerminal,
+}
+
+impl Harness<DefaultLangInterface> {
+    /// Create a harness with default language interface. See [`new`](Harness::new)
+    pub fn newd(width: u16, height: u16) -> Self {
+        Self::new(width, height)
+    }
+}
+
+impl<L: LangInterface> Harness<L> {
+    /// Create a harness with a screen of the given size, using `"> "` as the leader and `". "` as
+    /// the continued leader.
+    pub fn new(width: u16, height: u16) -> Self {
+        Self::with_leaders("> ", ". ", width, height)
+    }
+
+    /// Create a harness with a screen of the given size and the given leaders.
+    pub fn with_leaders(
+        leader: &'static str,
+        continued_leader: &'static str,
+        width: u16,
+        height: u16,
+    ) -> Self {
+        let input = ScriptedInput::new();
+        let terminal = VirtualTerminal::new(width, height);
+        let repl = Repl::with_io(
+            leader,
+            continued_leader,
+            64,
+            None,
+            input.clone(),
+            terminal.clone(),
+        );
+
+        Self {
+            repl,
+            input,
+            terminal,
+        }
+    }
+
+    /// Read the next command from the queued events, with the leader printed in the default colour.
+    pub fn read(&mut self) -> crate::Result<Signal> {
+        self.repl.next(Color::Reset)
+    }
+
+    /// Queue a key press for every character in `keys` and read the next command.
+    /// See [`ScriptedInput::type_str`].
+    pub fn run(&mut self, keys: &str) -> crate::Result<Signal> {
+        self.input.type_str(keys);
+        self.read()
+    }
+}
@@ -0,0 +1,13 @@
+use shelp::LangInterface;
+
+/// Indents by 4 for every unclosed brace
+pub struct Braces;
+
+impl LangInterface for Braces {
+    fn get_indent(lines: &[String]) -> usize {
+        let depth = lines.iter().fold(0, |depth, line| {
+            depth + line.matches('{').count() as isize - line.matches('}').count() as isize
+        });
+        depth.max(0) as usize * 4
+    }
+}
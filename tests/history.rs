@@ -0,0 +1,679 @@
+mod common;
+
+use common::Braces;
+use crossterm::event::KeyCode;
+use crossterm::style::Color;
+use shelp::testing::{Harness, ScriptedInput, TestRepl, VirtualTerminal};
+use shelp::{
+    HistoryControl, HistoryEntry, HistoryExpansion, HistoryStore, LineInput, Metadata, Repl, Signal,
+};
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+fn history_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("shelp-history-{}-{}", name, std::process::id()))
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    PathBuf::from(lock)
+}
+
+/// Removes a history file and the lock file left next to it
+fn remove_history(path: &Path) {
+    fs::remove_file(path).unwrap();
+    let _ = fs::remove_file(lock_path(path));
+}
+
+fn repl(path: &Path) -> (TestRepl<Braces>, ScriptedInput) {
+    let input = ScriptedInput::new();
+    let repl = Repl::with_io(
+        "> ",
+        ". ",
+        64,
+        Some(path.to_owned()),
+        input.clone(),
+        VirtualTerminal::new(40, 10),
+    );
+    (repl, input)
+}
+
+/// Recalls the `n`th most recent command from history
+fn recall(repl: &mut TestRepl<Braces>, input: &ScriptedInput, n: usize) -> Signal {
+    for _ in 0..n {
+        input.key(KeyCode::Up);
+    }
+    input.type_str("\n");
+    repl.next(Color::Reset).unwrap()
+}
+
+/// The lines of a history file after the header, split into the metadata and the command
+fn read_entries(path: &Path) -> Vec<(String, String)> {
+    let contents = fs::read_to_string(path).unwrap();
+    let mut lines = contents.lines();
+    assert_eq!(lines.next(), Some("#shelp-history v2"));
+
+    lines
+        .map(|line| {
+            let tab = line.find('\t').unwrap();
+            (line[..tab].to_owned(), line[tab + 1..].to_owned())
+        })
+        .collect()
+}
+
+fn commands(path: &Path) -> Vec<String> {
+    read_entries(path)
+        .into_iter()
+        .map(|(_, command)| command)
+        .collect()
+}
+
+#[test]
+fn commands_round_trip() {
+    let path = history_path("round-trip");
+    let contents = "#shelp-history v2\n;;;\tlet a = 2\n;;;\tif a {\\n---\\n    \\\\n\\r\\n}\n";
+    fs::write(&path, contents).unwrap();
+
+    {
+        let (mut repl, input) = repl(&path);
+        assert!(repl.history_error().is_none());
+        assert_eq!(
+            recall(&mut repl, &input, 1),
+            Signal::Command("if a {\n---\n    \\n\r\n}".to_owned())
+        );
+        input.type_str("print \\\n");
+        repl.next(Color::Reset).unwrap();
+    }
+
+    assert_eq!(
+        commands(&path),
+        [
+            "let a = 2",
+            "if a {\\n---\\n    \\\\n\\r\\n}",
+            "if a {\\n---\\n    \\\\n\\r\\n}",
+            "print \\\\",
+        ]
+    );
+    remove_history(&path);
+}
+
+#[test]
+fn old_format_is_migrated() {
+    let path = history_path("migrate");
+    fs::write(&path, "let a = 2\n---\nif a {\n}\n---\nprint a\n").unwrap();
+
+    {
+        let (mut repl, input) = repl(&path);
+        assert!(repl.history_error().is_none());
+        assert_eq!(
+            recall(&mut repl, &input, 1),
+            Signal::Command("print a".to_owned())
+        );
+        assert_eq!(
+            recall(&mut repl, &input, 3),
+            Signal::Command("if a {\n}".to_owned())
+        );
+    }
+
+    let entries = read_entries(&path);
+    assert_eq!(
+        entries,
+        [
+            (";;;", "let a = 2"),
+            (";;;", "if a {\\n}"),
+            (";;;", "print a"),
+            (&entries[3].0[..], "print a"),
+            (&entries[4].0[..], "if a {\\n}"),
+        ]
+        .iter()
+        .map(|&(m, c)| (m.to_owned(), c.to_owned()))
+        .collect::<Vec<_>>()
+    );
+    remove_history(&path);
+}
+
+#[test]
+fn invalid_file_is_not_overwritten() {
+    for contents in &[
+        "#shelp-history v2\n;;;\tok\n;;;\tbad \\q\n",
+        "#shelp-history v9\nok\n",
+    ] {
+        let path = history_path("invalid");
+        fs::write(&path, contents).unwrap();
+
+        {
+            let (mut repl, input) = repl(&path);
+            let error = repl.history_error().unwrap();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+            input.type_str("print 1\n");
+            repl.next(Color::Reset).unwrap();
+        }
+
+        assert_eq!(&fs::read_to_string(&path).unwrap(), contents);
+        remove_history(&path);
+    }
+}
+
+#[test]
+fn metadata_is_persisted() {
+    let path = history_path("metadata");
+    let _ = fs::remove_file(&path);
+
+    {
+        let (mut repl, input) = repl(&path);
+        input.type_str("print 1\n");
+        repl.next(Color::Reset).unwrap();
+        repl.report_status(true, Some(Duration::from_millis(1500)));
+        input.type_str("print 2\n");
+        repl.next(Color::Reset).unwrap();
+        repl.report_status(false, None);
+    }
+
+    let entries = read_entries(&path);
+    let cwd = std::env::current_dir().unwrap();
+    let fields: Vec<_> = entries[0].0.splitn(4, ';').collect();
+    assert!(fields[0].parse::<u64>().is_ok());
+    assert_eq!(&fields[1..], ["1500", "ok", &cwd.to_string_lossy()]);
+    assert_eq!(entries[1].0.split(';').nth(2), Some("failed"));
+
+    let (mut repl, input) = repl(&path);
+    let metadata = repl.history().get(1).unwrap().metadata();
+    assert!(metadata.time.unwrap() <= SystemTime::now());
+    assert_eq!(metadata.duration, Some(Duration::from_millis(1500)));
+    assert_eq!(metadata.success, Some(true));
+    assert_eq!(metadata.cwd.as_ref(), Some(&cwd));
+    assert_eq!(
+        repl.history().get(0).unwrap().metadata().success,
+        Some(false)
+    );
+    assert!(repl.history().get(2).is_none());
+
+    repl.set_skip_failed(true);
+    assert_eq!(
+        recall(&mut repl, &input, 1),
+        Signal::Command("print 1".to_owned())
+    );
+    drop(repl);
+    remove_history(&path);
+}
+
+#[test]
+fn sessions_are_merged() {
+    let path = history_path("merge");
+    fs::write(&path, "#shelp-history v2\n;;;\tshared\n").unwrap();
+
+    let (mut first, first_input) = repl(&path);
+    let (mut second, second_input) = repl(&path);
+
+    first_input.type_str("first\n");
+    first.next(Color::Reset).unwrap();
+    second_input.type_str("second\n");
+    second.next(Color::Reset).unwrap();
+
+    drop(first);
+    drop(second);
+
+    assert_eq!(commands(&path), ["shared", "first", "second"]);
+    let mut tmp = path.clone().into_os_string();
+    tmp.push(".tmp");
+    assert!(!Path::new(&tmp).exists());
+    remove_history(&path);
+}
+
+#[test]
+fn locked_history_is_not_saved() {
+    let path = history_path("locked");
+    fs::write(&path, "#shelp-history v2\n;;;\tsaved\n").unwrap();
+    // A lock file left by a session which crashed is not locked, so it does not stop saving
+    let lock = fs::File::create(lock_path(&path)).unwrap();
+
+    let (mut repl, input) = repl(&path);
+    input.type_str("print 1\n");
+    repl.next(Color::Reset).unwrap();
+
+    lock.lock().unwrap();
+    let error = repl.history_mut().save().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::WouldBlock);
+    assert_eq!(commands(&path), ["saved"]);
+
+    lock.unlock().unwrap();
+    repl.history_mut().save().unwrap();
+    assert_eq!(commands(&path), ["saved", "print 1"]);
+
+    drop(repl);
+    remove_history(&path);
+}
+
+#[cfg(unix)]
+#[test]
+fn permissions_and_symlinks_are_kept() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    let path = history_path("permissions");
+    let link = history_path("permissions-link");
+    let _ = fs::remove_file(&link);
+    fs::write(&path, "#shelp-history v2\n;;;\tprivate\n").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+    symlink(&path, &link).unwrap();
+
+    let (mut linked, input) = repl(&link);
+    input.type_str("secret\n");
+    linked.next(Color::Reset).unwrap();
+    drop(linked);
+
+    assert!(fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(commands(&path), ["private", "secret"]);
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // A new file is only readable by its owner
+    fs::remove_file(&link).unwrap();
+    remove_history(&path);
+    let (mut new, input) = repl(&path);
+    input.type_str("new\n");
+    new.next(Color::Reset).unwrap();
+    drop(new);
+
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    remove_history(&path);
+}
+
+#[test]
+fn incremental_history_saves_each_command() {
+    let path = history_path("incremental");
+    let _ = fs::remove_file(&path);
+
+    let (mut repl, input) = repl(&path);
+    repl.set_incremental_history(true);
+
+    input.type_str("print 1\n");
+    repl.next(Color::Reset).unwrap();
+    assert_eq!(commands(&path), ["print 1"]);
+
+    repl.report_status(false, Some(Duration::from_millis(20)));
+    input.type_str("print 2\n");
+    repl.next(Color::Reset).unwrap();
+
+    let entries = read_entries(&path);
+    assert_eq!(entries.len(), 2);
+    assert!(entries[0].0.contains(";20;failed;"));
+    assert_eq!(entries[1].1, "print 2");
+
+    drop(repl);
+    assert_eq!(commands(&path), ["print 1", "print 2"]);
+    remove_history(&path);
+}
+
+#[cfg(unix)]
+#[test]
+fn new_commands_are_appended() {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = history_path("append");
+    fs::write(&path, "#shelp-history v2\n;;;\tsaved\n").unwrap();
+    let inode = fs::metadata(&path).unwrap().ino();
+
+    let (mut repl, input) = repl(&path);
+    repl.set_incremental_history(true);
+    input.type_str("print 1\n");
+    repl.next(Color::Reset).unwrap();
+    drop(repl);
+
+    // The file is added to rather than replaced
+    assert_eq!(fs::metadata(&path).unwrap().ino(), inode);
+    assert_eq!(commands(&path), ["saved", "print 1"]);
+    remove_history(&path);
+}
+
+#[test]
+fn history_control_is_applied_on_load_and_push() {
+    let path = history_path("control");
+    fs::write(
+        &path,
+        "#shelp-history v2\n;;;\ta\n;;;\ta\n;;;\t secret\n;;;\tb\n;;;\tpassword=1\n;;;\ta\n",
+    )
+    .unwrap();
+
+    {
+        let (mut repl, input) = repl(&path);
+        repl.set_history_control(HistoryControl {
+            ignore_space: true,
+            erase_dups: true,
+            ..HistoryControl::default()
+        });
+        repl.set_history_filter(|command| !command.contains("password"));
+
+        assert_eq!(
+            recall(&mut repl, &input, 2),
+            Signal::Command("b".to_owned())
+        );
+        input.type_str(" hidden\n");
+        repl.next(Color::Reset).unwrap();
+        assert_eq!(
+            recall(&mut repl, &input, 2),
+            Signal::Command("a".to_owned())
+        );
+    }
+
+    assert_eq!(commands(&path), ["b", "a"]);
+    remove_history(&path);
+}
+
+#[test]
+fn consecutive_duplicates_are_ignored() {
+    let path = history_path("dups");
+    let _ = fs::remove_file(&path);
+
+    {
+        let (mut repl, input) = repl(&path);
+        repl.set_history_control(HistoryControl {
+            ignore_dups: true,
+            ..HistoryControl::default()
+        });
+        for command in &["x\n", "x\n", "y\n", "x\n"] {
+            input.type_str(command);
+            repl.next(Color::Reset).unwrap();
+        }
+        assert_eq!(
+            recall(&mut repl, &input, 1),
+            Signal::Command("x".to_owned())
+        );
+    }
+
+    assert_eq!(commands(&path), ["x", "y", "x"]);
+    remove_history(&path);
+}
+
+#[test]
+fn status_of_ignored_command_is_not_recorded() {
+    let path = history_path("ignored-status");
+    let _ = fs::remove_file(&path);
+
+    {
+        let (mut repl, input) = repl(&path);
+        repl.set_history_control(HistoryControl {
+            ignore_space: true,
+            ..HistoryControl::default()
+        });
+
+        input.type_str("good\n");
+        repl.next(Color::Reset).unwrap();
+        repl.report_status(true, Some(Duration::from_millis(5)));
+        input.type_str(" bad\n");
+        repl.next(Color::Reset).unwrap();
+        repl.report_status(false, Some(Duration::from_millis(999)));
+
+        let metadata = repl.history().get(0).unwrap().metadata();
+        assert_eq!(metadata.success, Some(true));
+        assert_eq!(metadata.duration, Some(Duration::from_millis(5)));
+    }
+
+    let entries = read_entries(&path);
+    assert_eq!(entries.len(), 1);
+    assert!(entries[0].0.contains(";5;ok;"));
+    assert_eq!(entries[0].1, "good");
+    remove_history(&path);
+}
+
+#[test]
+fn status_of_piped_command_is_not_recorded() {
+    let path = history_path("piped-status");
+    fs::write(&path, "#shelp-history v2\n;;;\tsaved\n").unwrap();
+
+    {
+        let input = LineInput::new("piped\n".as_bytes());
+        let mut repl =
+            Repl::<Braces, _, _>::with_io("> ", ". ", 64, Some(path.clone()), input, Vec::new());
+        repl.next(Color::Reset).unwrap();
+        repl.report_status(false, Some(Duration::from_millis(999)));
+        assert_eq!(repl.history().len(), 1);
+    }
+
+    assert_eq!(
+        read_entries(&path),
+        [(";;;".to_owned(), "saved".to_owned())]
+    );
+    remove_history(&path);
+}
+
+#[test]
+fn history_is_edited() {
+    let path = history_path("edit");
+    fs::write(&path, "#shelp-history v2\n;;;\ta\n;;;\tpassword\n;;;\tb\n").unwrap();
+
+    {
+        let (mut repl, _) = repl(&path);
+        let history = repl.history_mut();
+        assert_eq!(history.len(), 3);
+
+        let found: Vec<_> = history.search("pass").map(|(i, _)| i).collect();
+        assert_eq!(found, [1]);
+        assert_eq!(history.remove(1).unwrap().command(), "password");
+
+        history.push("c\n  d", Metadata::default());
+        history.insert(3, "z", Metadata::default());
+        let commands: Vec<_> = history.iter().map(HistoryEntry::command).collect();
+        assert_eq!(commands, ["c\n  d", "b", "a", "z"]);
+        assert_eq!(history.get(0).unwrap().lines(), ["c", "  d"]);
+
+        history.set_capacity(2);
+        let oldest: Vec<_> = history.iter().rev().map(HistoryEntry::command).collect();
+        assert_eq!(oldest, ["b", "c\n  d"]);
+
+        history.save().unwrap();
+    }
+
+    assert_eq!(commands(&path), ["b", "c\\n  d"]);
+
+    let (mut repl, _) = repl(&path);
+    repl.history_mut().clear();
+    assert!(repl.history().is_empty());
+    drop(repl);
+    assert!(commands(&path).is_empty());
+    remove_history(&path);
+}
+
+#[test]
+fn inserted_commands_are_not_saved() {
+    let path = history_path("insert");
+    fs::write(&path, "#shelp-history v2\n;;;\tsaved\n").unwrap();
+
+    let (mut repl, input) = repl(&path);
+    repl.history_mut().insert(0, "seeded", Metadata::default());
+    repl.history_mut().insert(2, "example", Metadata::default());
+    input.type_str("run\n");
+    repl.next(Color::Reset).unwrap();
+    repl.history_mut().save().unwrap();
+
+    let commands_in = |repl: &TestRepl<Braces>| {
+        repl.history()
+            .iter()
+            .map(HistoryEntry::command)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(commands_in(&repl), ["run", "seeded", "saved", "example"]);
+    assert_eq!(commands(&path), ["saved", "run"]);
+
+    repl.history_mut().reload().unwrap();
+    assert_eq!(commands_in(&repl), ["run", "saved"]);
+    drop(repl);
+
+    assert_eq!(commands(&path), ["saved", "run"]);
+    remove_history(&path);
+}
+
+#[test]
+fn history_is_reloaded() {
+    let path = history_path("reload");
+    let _ = fs::remove_file(&path);
+
+    let (mut first, first_input) = repl(&path);
+    let (mut second, _) = repl(&path);
+
+    first_input.type_str("first\n");
+    first.next(Color::Reset).unwrap();
+    first.history_mut().save().unwrap();
+
+    second.history_mut().push("second", Metadata::default());
+    second.history_mut().reload().unwrap();
+    let commands: Vec<_> = second.history().iter().map(HistoryEntry::command).collect();
+    assert_eq!(commands, ["second", "first"]);
+
+    drop(first);
+    drop(second);
+    remove_history(&path);
+}
+
+#[test]
+fn history_is_expanded() {
+    let mut harness = Harness::newd(40, 20);
+    harness.repl.set_history_expansion(HistoryExpansion::On);
+    let mut run = |keys: &str| match harness.run(keys).unwrap() {
+        Signal::Command(command) => command,
+        signal => panic!("expected a command, got {:?}", signal),
+    };
+
+    assert_eq!(run("echo a b c\n"), "echo a b c");
+    assert_eq!(run("!!\n"), "echo a b c");
+    assert_eq!(run("x !$ !^ !*\n"), "x c a a b c");
+    assert_eq!(run("!-2:2 !1:0 !?a a?\n"), "b echo x c a a b c");
+    assert_eq!(run("^echo^print^ d\n"), "b print x c a a b c d");
+    assert_eq!(run("!x\n"), "x c a a b c");
+    assert_eq!(run("a != b '!!' \\!! !\n"), "a != b '!!' \\!! !");
+
+    assert_eq!(harness.terminal.row(1), "> !!");
+    assert_eq!(harness.terminal.row(2), "echo a b c");
+}
+
+#[test]
+fn expansion_follows_shell_quoting() {
+    let mut harness = Harness::newd(40, 20);
+    harness.repl.set_history_expansion(HistoryExpansion::On);
+    let mut run = |keys: &str| match harness.run(keys).unwrap() {
+        Signal::Command(command) => command,
+        signal => panic!("expected a command, got {:?}", signal),
+    };
+
+    assert_eq!(run("echo a\n"), "echo a");
+    assert_eq!(run("(!ec)\n"), "(echo a)");
+    assert_eq!(run("!ec;ls|!(x)\n"), "echo a;ls|!(x)");
+    assert_eq!(run("echo \"it's !-2\"\n"), "echo \"it's (echo a)\"");
+    assert_eq!(run("echo \"hi!\" '!!'\n"), "echo \"hi!\" '!!'");
+}
+
+#[test]
+fn expanded_command_is_verified() {
+    let mut harness = Harness::<Braces>::new(40, 10);
+    harness.repl.set_history_expansion(HistoryExpansion::Verify);
+
+    assert_eq!(
+        harness.run("if {\n}\n").unwrap(),
+        Signal::Command("if {\n    }".to_owned())
+    );
+    harness.input.type_str("!!\n");
+    harness.input.type_str(" else {\n}\n");
+    assert_eq!(
+        harness.read().unwrap(),
+        Signal::Command("if {\n    } else {\n    }".to_owned())
+    );
+    assert_eq!(harness.repl.history().len(), 2);
+}
+
+#[test]
+fn failed_expansion_is_edited_again() {
+    let mut harness = Harness::newd(40, 10);
+    harness.repl.set_history_expansion(HistoryExpansion::On);
+
+    assert!(harness.run("!nope\n").is_err());
+    assert_eq!(
+        harness.terminal.rows()[..3],
+        ["> !nope", "!nope: event not found", "> !nope"]
+    );
+    assert!(harness.repl.history().is_empty());
+}
+
+/// Keeps history in memory, counting how it is changed
+#[derive(Clone, Default)]
+struct MemoryStore {
+    entries: Rc<RefCell<Vec<HistoryEntry>>>,
+    appends: Rc<Cell<usize>>,
+    rewrites: Rc<Cell<usize>>,
+}
+
+impl MemoryStore {
+    fn commands(&self) -> Vec<String> {
+        self.entries
+            .borrow()
+            .iter()
+            .map(HistoryEntry::command)
+            .collect()
+    }
+}
+
+impl HistoryStore for MemoryStore {
+    fn load(&mut self) -> std::io::Result<Vec<HistoryEntry>> {
+        Ok(self.entries.borrow().clone())
+    }
+
+    fn append(&mut self, entries: &[HistoryEntry]) -> std::io::Result<()> {
+        self.appends.set(self.appends.get() + 1);
+        self.entries.borrow_mut().extend_from_slice(entries);
+        Ok(())
+    }
+
+    fn rewrite(&mut self, entries: &[HistoryEntry]) -> std::io::Result<()> {
+        self.rewrites.set(self.rewrites.get() + 1);
+        *self.entries.borrow_mut() = entries.to_vec();
+        Ok(())
+    }
+}
+
+#[test]
+fn history_store_is_pluggable() {
+    let store = MemoryStore::default();
+    store.entries.borrow_mut().extend(vec![
+        HistoryEntry::new("a", Metadata::default()),
+        HistoryEntry::new("b {\n}", Metadata::default()),
+    ]);
+
+    let mut harness = Harness::<Braces>::new(40, 10);
+    harness.run("x\n").unwrap();
+    harness.repl.set_history_store(store.clone());
+    assert!(harness.repl.history_error().is_none());
+
+    let commands: Vec<_> = harness
+        .repl
+        .history()
+        .iter()
+        .map(HistoryEntry::command)
+        .collect();
+    assert_eq!(commands, ["x", "b {\n}", "a"]);
+
+    harness.run("y\n").unwrap();
+    harness.repl.history_mut().save().unwrap();
+    assert_eq!(store.commands(), ["a", "b {\n}", "x", "y"]);
+    assert_eq!((store.appends.get(), store.rewrites.get()), (1, 0));
+
+    harness.repl.history_mut().remove(2);
+    harness.repl.history_mut().save().unwrap();
+    assert_eq!(store.commands(), ["a", "x", "y"]);
+    assert_eq!((store.appends.get(), store.rewrites.get()), (1, 1));
+
+    let found = harness
+        .repl
+        .history_mut()
+        .store_mut()
+        .unwrap()
+        .search("x")
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].command(), "x");
+}
@@ -0,0 +1,891 @@
+mod common;
+
+use common::Braces;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Color, Colorize};
+use shelp::testing::{Harness, ScriptedInput, VirtualTerminal};
+use shelp::{
+    Action, Completion, HistoryNavigation, InterruptPolicy, Keymap, LangInterface, LineInput, Repl,
+    ReplIter, Result, Signal,
+};
+use std::io::Write;
+
+fn command(command: &str) -> Signal {
+    Signal::Command(command.to_owned())
+}
+
+#[test]
+fn default_type_parameters() {
+    // Types named before the input and output could be changed still compile
+    fn iter(repl: Repl<Braces>) -> ReplIter<Braces> {
+        repl.iter(Color::Reset)
+    }
+    let _: fn(Repl<Braces>) -> ReplIter<Braces> = iter;
+    let _: Option<ReplIter> = None;
+}
+
+/// Colours numbers magenta
+struct Numbers;
+
+impl LangInterface for Numbers {
+    fn print_line<W: Write>(stdout: &mut W, lines: &[String], index: usize) -> Result<()> {
+        for i in lines[index].chars() {
+            if i.is_numeric() {
+                write!(stdout, "{}", i.magenta())?;
+            } else {
+                write!(stdout, "{}", i)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn returns_typed_command() {
+    let mut harness = Harness::newd(20, 5);
+
+    assert_eq!(harness.run("print 1\n").unwrap(), command("print 1"));
+    assert_eq!(harness.terminal.row(0), "> print 1");
+    assert_eq!(harness.terminal.cursor(), (0, 1));
+
+    assert_eq!(harness.run("print 2\n").unwrap(), command("print 2"));
+    assert_eq!(harness.terminal.contents(), "> print 1\n> print 2");
+}
+
+#[test]
+fn cursor_follows_movement() {
+    let mut harness = Harness::newd(20, 5);
+    harness.input.type_str("héllo");
+    harness.input.key(KeyCode::Left);
+    harness.input.key(KeyCode::Left);
+    harness.input.type_str("_");
+
+    assert!(harness.read().is_err());
+    assert_eq!(harness.terminal.row(0), "> hél_lo");
+    assert_eq!(harness.terminal.cursor(), (6, 0));
+}
+
+#[test]
+fn empty_lines_are_skipped() {
+    let mut harness = Harness::newd(20, 3);
+
+    assert_eq!(harness.run("\n  \n\nok\n").unwrap(), command("ok"));
+    assert_eq!(harness.terminal.rows(), vec![">", "> ok", ""]);
+}
+
+#[test]
+fn multi_line_blocks_are_indented() {
+    let mut harness = Harness::<Braces>::new(20, 5);
+
+    let signal = harness.run("if x {\ny\n}\n").unwrap();
+    assert_eq!(signal, command("if x {\n    y\n    }"));
+    assert_eq!(harness.terminal.contents(), "> if x {\n.     y\n.     }");
+    assert_eq!(harness.terminal.cursor(), (0, 3));
+}
+
+#[test]
+fn backspace_joins_lines() {
+    let mut harness = Harness::<Braces>::new(20, 5);
+    harness.input.type_str("a {\nb");
+    harness.input.key(KeyCode::Home);
+    harness.input.key(KeyCode::Backspace);
+
+    assert!(harness.read().is_err());
+    assert_eq!(harness.terminal.contents(), "> a {    b");
+    assert_eq!(harness.terminal.cursor(), (5, 0));
+}
+
+#[test]
+fn history_is_recalled() {
+    let mut harness = Harness::<Braces>::new(20, 8);
+    harness.run("one\n").unwrap();
+    harness.run("f {\nx\n}\n").unwrap();
+
+    harness.input.key(KeyCode::Up);
+    assert!(harness.read().is_err());
+    assert_eq!(
+        harness.terminal.rows()[4..7],
+        ["> f {", ".     x", ".     }"]
+    );
+    assert_eq!(harness.terminal.cursor(), (7, 6));
+}
+
+#[test]
+fn history_blocks_are_skipped() {
+    let mut harness = Harness::<Braces>::new(20, 8);
+    harness.run("one\n").unwrap();
+    harness.run("f {\nx\n}\n").unwrap();
+
+    harness.input.key(KeyCode::Up);
+    harness.input.key(KeyCode::PageUp);
+    assert!(harness.read().is_err());
+    assert_eq!(harness.terminal.rows()[4..7], ["> one", "", ""]);
+    assert_eq!(harness.terminal.cursor(), (5, 4));
+}
+
+#[test]
+fn edited_history_is_returned() {
+    let mut harness = Harness::<Braces>::new(20, 8);
+    harness.run("one\n").unwrap();
+    harness.run("f {\nx\n}\n").unwrap();
+
+    harness.input.key(KeyCode::PageUp);
+    harness.input.key(KeyCode::PageUp);
+    harness.input.key(KeyCode::PageDown);
+    harness.input.key(KeyCode::Up);
+    harness.input.key(KeyCode::Up);
+    harness.input.type_str("!\n");
+    assert_eq!(harness.read().unwrap(), command("one!"));
+}
+
+#[test]
+fn screen_scrolls_at_bottom() {
+    let mut harness = Harness::<Braces>::new(20, 3);
+    harness.run("a\n").unwrap();
+    harness.run("b\n").unwrap();
+    harness.run("c {\n}\n").unwrap();
+
+    assert_eq!(harness.terminal.rows(), vec!["> c {", ".     }", ""]);
+    assert_eq!(harness.terminal.cursor(), (0, 2));
+}
+
+#[test]
+fn leader_and_highlighting_colours() {
+    let mut harness = Harness::<Numbers>::new(20, 3);
+    harness.input.type_str("a1");
+    assert!(harness.repl.next(Color::Green).is_err());
+
+    let cell = |x| harness.terminal.cell(x, 0);
+    assert_eq!(cell(0).fg, Color::Green);
+    assert_eq!(cell(2).fg, Color::Reset);
+    assert_eq!(cell(3).fg, Color::Magenta);
+}
+
+#[test]
+fn clear_screen() {
+    let mut harness = Harness::newd(20, 3);
+    harness.run("one\n").unwrap();
+    harness.input.type_str("two");
+    harness
+        .input
+        .key_with(KeyCode::Char('l'), KeyModifiers::CONTROL);
+
+    assert!(harness.read().is_err());
+    assert_eq!(harness.terminal.contents(), "> two");
+    assert_eq!(harness.terminal.cursor(), (5, 0));
+}
+
+#[test]
+fn exit_keyword_is_signalled() {
+    let mut harness = Harness::newd(20, 3);
+    harness.repl.set_exit_keyword("quit");
+
+    assert_eq!(harness.run("exit\n").unwrap(), command("exit"));
+    assert_eq!(harness.run("quit\n").unwrap(), Signal::Exit);
+    assert_eq!(harness.terminal.cursor(), (0, 2));
+}
+
+#[test]
+fn ctrl_c_is_signalled() {
+    let mut harness = Harness::<Braces>::new(20, 5);
+    harness.input.type_str("a {\nb");
+    harness.input.key(KeyCode::Up);
+    harness
+        .input
+        .key_with(KeyCode::Char('c'), KeyModifiers::CONTROL);
+
+    assert_eq!(harness.read().unwrap(), Signal::Interrupted);
+    // The cursor is moved below the whole block
+    assert_eq!(harness.terminal.rows()[..2], ["> a {", ".     b^C"]);
+    assert_eq!(harness.terminal.cursor(), (0, 2));
+
+    // Interrupted commands are not added to history
+    harness.input.key(KeyCode::Up);
+    assert_eq!(harness.run("x\n").unwrap(), command("x"));
+}
+
+#[test]
+fn ctrl_c_twice_on_empty_prompt_exits() {
+    let mut harness = Harness::newd(20, 8);
+    harness
+        .repl
+        .set_interrupt_policy(InterruptPolicy::ExitAfter(2));
+    let input = harness.input.clone();
+    let ctrl_c = || input.key_with(KeyCode::Char('c'), KeyModifiers::CONTROL);
+
+    harness.input.type_str("abc");
+    ctrl_c();
+    assert_eq!(harness.read().unwrap(), Signal::Interrupted);
+
+    ctrl_c();
+    assert_eq!(harness.read().unwrap(), Signal::Interrupted);
+
+    // Any other key starts the count again
+    harness.input.type_str("a");
+    harness.input.key(KeyCode::Backspace);
+    ctrl_c();
+    assert_eq!(harness.read().unwrap(), Signal::Interrupted);
+
+    ctrl_c();
+    assert_eq!(harness.read().unwrap(), Signal::Exit);
+}
+
+#[test]
+fn ctrl_d_on_empty_prompt_is_eof() {
+    let mut harness = Harness::newd(20, 3);
+    harness.input.type_str("abc");
+    harness.input.key(KeyCode::Home);
+    harness
+        .input
+        .key_with(KeyCode::Char('d'), KeyModifiers::CONTROL);
+    harness.input.type_str("\n");
+    assert_eq!(harness.read().unwrap(), command("bc"));
+
+    harness
+        .input
+        .key_with(KeyCode::Char('d'), KeyModifiers::CONTROL);
+    assert_eq!(harness.read().unwrap(), Signal::Eof);
+}
+
+#[test]
+fn iterator_ends_on_eof() {
+    let harness = Harness::newd(20, 5);
+    harness.input.type_str("a\n");
+    harness
+        .input
+        .key_with(KeyCode::Char('c'), KeyModifiers::CONTROL);
+    harness.input.type_str("b\n");
+    harness
+        .input
+        .key_with(KeyCode::Char('d'), KeyModifiers::CONTROL);
+    harness.input.type_str("c\n");
+
+    let commands: Vec<_> = harness.repl.iter(Color::Reset).collect();
+    assert_eq!(commands, ["a", "b"]);
+}
+
+#[test]
+fn non_interactive_input_is_read_by_line() {
+    let input = LineInput::new("a\n\nif x {\n  y\n}\nclear\nb {\nexit\n".as_bytes());
+    let terminal = VirtualTerminal::new(20, 5);
+    let mut repl = Repl::<Braces, _, _>::with_io("> ", ". ", 64, None, input, terminal.clone());
+
+    assert_eq!(repl.next(Color::Reset).unwrap(), command("a"));
+    assert_eq!(repl.next(Color::Reset).unwrap(), command("if x {\n  y\n}"));
+    // An unfinished block is given once the input ends
+    assert_eq!(repl.next(Color::Reset).unwrap(), command("b {\nexit"));
+    assert_eq!(repl.next(Color::Reset).unwrap(), Signal::Eof);
+
+    drop(repl);
+    assert_eq!(terminal.contents(), "");
+}
+
+#[test]
+fn non_interactive_exit_keyword() {
+    let input = LineInput::new("a\nexit\nb\n".as_bytes());
+    let repl = Repl::<Braces, _, _>::with_io("> ", ". ", 64, None, input, Vec::new());
+
+    let commands: Vec<_> = repl.iter(Color::Reset).collect();
+    assert_eq!(commands, ["a"]);
+}
+
+/// Treats `::` paths as single words
+struct Paths;
+
+impl LangInterface for Paths {
+    fn is_word_char(chr: char) -> bool {
+        chr.is_alphanumeric() || chr == '_' || chr == ':'
+    }
+}
+
+#[test]
+fn word_movement() {
+    let mut harness = Harness::newd(30, 3);
+    harness.input.type_str("foo::bar(baz)");
+    harness.input.key_with(KeyCode::Left, KeyModifiers::CONTROL);
+    harness.input.key_with(KeyCode::Left, KeyModifiers::CONTROL);
+    harness.input.type_str("_");
+    harness
+        .input
+        .key_with(KeyCode::Char('f'), KeyModifiers::ALT);
+    harness
+        .input
+        .key_with(KeyCode::Char('f'), KeyModifiers::ALT);
+    harness.input.type_str("!");
+    harness
+        .input
+        .key_with(KeyCode::Char('b'), KeyModifiers::ALT);
+    harness
+        .input
+        .key_with(KeyCode::Right, KeyModifiers::CONTROL);
+    harness.input.type_str("?\n");
+
+    assert_eq!(harness.read().unwrap(), command("foo::_bar(baz?!)"));
+}
+
+#[test]
+fn word_deletion() {
+    let mut harness = Harness::newd(30, 3);
+    harness.input.type_str("foo::bar(baz) qux");
+    harness
+        .input
+        .key_with(KeyCode::Char('w'), KeyModifiers::CONTROL);
+    harness
+        .input
+        .key_with(KeyCode::Backspace, KeyModifiers::ALT);
+    harness.input.key(KeyCode::Home);
+    harness
+        .input
+        .key_with(KeyCode::Char('d'), KeyModifiers::ALT);
+    harness.input.type_str("\n");
+
+    assert_eq!(harness.read().unwrap(), command("::bar("));
+}
+
+#[test]
+fn word_boundaries_from_lang_interface() {
+    let mut harness = Harness::<Paths>::new(30, 3);
+    harness.input.type_str("use foo::bar");
+    harness
+        .input
+        .key_with(KeyCode::Char('\x7f'), KeyModifiers::ALT);
+    harness.input.type_str("baz\n");
+
+    assert_eq!(harness.read().unwrap(), command("use baz"));
+}
+
+#[test]
+fn kill_and_yank() {
+    let mut harness = Harness::newd(30, 3);
+    let ctrl = |chr| {
+        harness
+            .input
+            .key_with(KeyCode::Char(chr), KeyModifiers::CONTROL)
+    };
+
+    harness.input.type_str("one two three");
+    ctrl('w');
+    ctrl('w');
+    harness.input.key(KeyCode::Home);
+    harness
+        .input
+        .key_with(KeyCode::Char('d'), KeyModifiers::ALT);
+    ctrl('y');
+    ctrl('y');
+    harness
+        .input
+        .key_with(KeyCode::Char('y'), KeyModifiers::ALT);
+    harness.input.type_str("\n");
+
+    // Consecutive kills are joined
+    assert_eq!(harness.read().unwrap(), command("onetwo three "));
+}
+
+#[test]
+fn kill_ring_persists_and_cycles() {
+    let mut harness = Harness::newd(30, 5);
+    let input = harness.input.clone();
+    let ctrl = |chr| input.key_with(KeyCode::Char(chr), KeyModifiers::CONTROL);
+
+    harness.input.type_str("abc def");
+    harness.input.key_with(KeyCode::Left, KeyModifiers::CONTROL);
+    ctrl('k');
+    harness.input.type_str("|");
+    ctrl('u');
+    harness.input.type_str("x\n");
+    assert_eq!(harness.read().unwrap(), command("x"));
+
+    ctrl('y');
+    harness
+        .input
+        .key_with(KeyCode::Char('y'), KeyModifiers::ALT);
+    harness.input.type_str("-");
+    ctrl('y');
+    harness
+        .input
+        .key_with(KeyCode::Char('y'), KeyModifiers::ALT);
+    harness
+        .input
+        .key_with(KeyCode::Char('y'), KeyModifiers::ALT);
+    harness.input.type_str("\n");
+    assert_eq!(harness.read().unwrap(), command("def-abc |"));
+}
+
+#[test]
+fn undo_groups_typed_characters() {
+    let mut harness = Harness::<Braces>::new(30, 6);
+    let input = harness.input.clone();
+    let undo = || input.key_with(KeyCode::Char('z'), KeyModifiers::CONTROL);
+    let redo = || input.key_with(KeyCode::Char('/'), KeyModifiers::ALT);
+
+    harness.input.type_str("if x {\nfoo");
+    harness.input.key(KeyCode::Left);
+    harness.input.type_str("bar");
+    // Undoes "bar", "foo" and then the new line
+    undo();
+    undo();
+    undo();
+    redo();
+    harness.input.type_str("baz\n}\n");
+
+    assert_eq!(harness.read().unwrap(), command("if x {\n    baz\n    }"));
+    assert_eq!(harness.terminal.row(1), ".     baz");
+}
+
+#[test]
+fn undo_edit_of_history_entry() {
+    let mut harness = Harness::newd(30, 6);
+    let input = harness.input.clone();
+    let undo = || input.key_with(KeyCode::Char('7'), KeyModifiers::CONTROL);
+
+    harness.run("first\n").unwrap();
+
+    harness.input.type_str("dra");
+    harness.input.key(KeyCode::Up);
+    harness
+        .input
+        .key_with(KeyCode::Char('w'), KeyModifiers::CONTROL);
+    harness.input.type_str("oops");
+    undo();
+    undo();
+    assert_eq!(harness.run("\n").unwrap(), command("first"));
+
+    harness.input.type_str("dra");
+    harness.input.key(KeyCode::Up);
+    harness.input.type_str("oops");
+    undo();
+    undo();
+    assert_eq!(harness.run("ft\n").unwrap(), command("draft"));
+}
+
+#[test]
+fn configured_undo_keys() {
+    let mut harness = Harness::newd(30, 3);
+    harness.repl.set_undo_keys(
+        vec![KeyEvent::new(KeyCode::Char('u'), KeyModifiers::ALT)],
+        vec![KeyEvent::new(KeyCode::Char('r'), KeyModifiers::ALT)],
+    );
+    let input = harness.input.clone();
+    let alt = |chr| input.key_with(KeyCode::Char(chr), KeyModifiers::ALT);
+
+    harness.input.type_str("abc");
+    alt('u');
+    alt('u');
+    alt('r');
+    harness.input.type_str("d\n");
+
+    assert_eq!(harness.read().unwrap(), command("abcd"));
+}
+
+#[test]
+fn keymap_rebinds_keys() {
+    let mut harness = Harness::newd(30, 3);
+    let mut keymap = Keymap::default();
+    keymap.unbind(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE));
+    keymap.bind(
+        KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL),
+        Action::LineStart,
+    );
+    harness.repl.set_keymap(keymap);
+
+    harness.input.type_str("b\tc");
+    harness
+        .input
+        .key_with(KeyCode::Char('a'), KeyModifiers::CONTROL);
+    // Unbound keys with modifiers are handled like the key without them
+    harness.input.key_with(KeyCode::Right, KeyModifiers::ALT);
+    harness.input.type_str("_\n");
+
+    assert_eq!(harness.read().unwrap(), command("b_c"));
+}
+
+#[test]
+fn keymap_installed_on_creation() {
+    let input = ScriptedInput::new();
+    let mut keymap = Keymap::default();
+    keymap.bind(
+        KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL),
+        Action::LineStart,
+    );
+    let mut repl = Repl::<Braces, _, _>::with_io(
+        "> ",
+        ". ",
+        64,
+        None,
+        input.clone(),
+        VirtualTerminal::new(30, 3),
+    )
+    .with_keymap(keymap);
+
+    input.type_str("b");
+    input.key_with(KeyCode::Char('a'), KeyModifiers::CONTROL);
+    input.type_str("a\n");
+
+    assert_eq!(repl.next(Color::Reset).unwrap(), command("ab"));
+}
+
+#[test]
+fn keymap_custom_action() {
+    let mut harness = Harness::newd(30, 3);
+    harness.repl.keymap_mut().bind(
+        KeyEvent::new(KeyCode::F(1), KeyModifiers::NONE),
+        Action::custom(|editor| {
+            if editor.line().is_empty() {
+                Some(Action::Insert('?'))
+            } else {
+                Some(Action::Enter)
+            }
+        }),
+    );
+
+    harness.input.key(KeyCode::F(1));
+    harness.input.type_str("x");
+    harness.input.key(KeyCode::F(1));
+
+    assert_eq!(harness.read().unwrap(), command("?x"));
+}
+
+#[test]
+fn custom_action_prints_above_prompt() {
+    let mut harness = Harness::newd(30, 4);
+    harness.repl.keymap_mut().bind(
+        KeyEvent::new(KeyCode::F(1), KeyModifiers::NONE),
+        Action::custom(|editor| {
+            let help = format!("help: {}", editor.word());
+            editor.print(help);
+            None
+        }),
+    );
+
+    harness.input.type_str("let foo");
+    harness.input.key(KeyCode::Left);
+    harness.input.key(KeyCode::F(1));
+
+    assert!(harness.read().is_err());
+    assert_eq!(harness.terminal.contents(), "help: foo\n> let foo");
+    assert_eq!(harness.terminal.cursor(), (8, 1));
+}
+
+#[test]
+fn custom_action_edits_and_submits() {
+    let mut harness = Harness::<Braces>::new(30, 4);
+    harness.repl.keymap_mut().bind(
+        KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL),
+        Action::custom(|editor| {
+            let formatted = editor.lines().iter().map(|line| line.trim().to_owned());
+            editor.set_lines(formatted.collect());
+            editor.set_cursor(0, 0);
+            editor.insert("fn f() ");
+            None
+        }),
+    );
+    harness.repl.keymap_mut().bind(
+        KeyEvent::new(KeyCode::F(2), KeyModifiers::NONE),
+        Action::custom(|editor| {
+            editor.submit();
+            None
+        }),
+    );
+    let input = harness.input.clone();
+    let format = || input.key_with(KeyCode::Char('r'), KeyModifiers::CONTROL);
+
+    harness.input.type_str("{\n  x");
+    format();
+    harness.input.key(KeyCode::F(2));
+    assert_eq!(harness.read().unwrap(), command("fn f() {\nx"));
+
+    // The formatting can be undone like any other edit
+    harness.input.type_str(" y");
+    format();
+    harness
+        .input
+        .key_with(KeyCode::Char('z'), KeyModifiers::CONTROL);
+    harness.input.key(KeyCode::Enter);
+    assert_eq!(harness.read().unwrap(), command(" y"));
+}
+
+/// Completes the word before the cursor from a list of keywords
+struct Keywords;
+
+impl LangInterface for Keywords {
+    fn get_indent(_lines: &[String]) -> usize {
+        0
+    }
+
+    fn complete(lines: &[String], lineno: usize, charno: usize) -> Vec<Completion> {
+        let chars: Vec<_> = lines[lineno].chars().take(charno).collect();
+        let start = chars
+            .iter()
+            .rposition(|chr| !chr.is_alphanumeric())
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let word: String = chars[start..].iter().collect();
+
+        ["print", "println", "private", "return"]
+            .iter()
+            .filter(|keyword| keyword.starts_with(&word))
+            .map(|keyword| Completion::new(*keyword, start..charno))
+            .collect()
+    }
+}
+
+/// Gives candidates with ranges which cannot be used, along with one which can
+struct BadRanges;
+
+impl LangInterface for BadRanges {
+    fn complete(_lines: &[String], _lineno: usize, charno: usize) -> Vec<Completion> {
+        vec![
+            Completion::new("reversed", charno..0),
+            Completion::new("past end", 0..charno + 5),
+            Completion::new("ok", charno..charno),
+        ]
+    }
+}
+
+#[test]
+fn completions_with_bad_ranges_are_ignored() {
+    let mut harness = Harness::<BadRanges>::new(30, 4);
+
+    assert_eq!(harness.run("é\t\n").unwrap(), command("éok"));
+}
+
+#[test]
+fn tab_completes_single_candidate() {
+    let mut harness = Harness::<Keywords>::new(30, 4);
+
+    assert_eq!(harness.run("x; ret\t 1\n").unwrap(), command("x; return 1"));
+    // Tab in the indentation of a line still indents
+    assert_eq!(harness.run("\tret\t\n").unwrap(), command("    return"));
+}
+
+#[test]
+fn tab_shows_completion_menu() {
+    let mut harness = Harness::<Keywords>::new(30, 6);
+    harness.input.type_str("pr\t");
+
+    assert!(harness.read().is_err());
+    // The common prefix is inserted
+    assert_eq!(
+        harness.terminal.contents(),
+        "> pri\n  print\n  println\n  private"
+    );
+    assert_eq!(harness.terminal.cursor(), (5, 0));
+
+    let mut harness = Harness::<Keywords>::new(30, 6);
+    harness.input.type_str("pr\t\t\t");
+
+    assert!(harness.read().is_err());
+    assert_eq!(harness.terminal.row(0), "> println");
+    assert_eq!(harness.terminal.cursor(), (9, 0));
+}
+
+#[test]
+fn completion_menu_is_navigated() {
+    let mut harness = Harness::<Keywords>::new(30, 6);
+    harness.input.type_str("pr\t\t\t");
+    // Shift-Tab goes back, and Enter accepts the candidate without giving the command
+    harness.input.key(KeyCode::BackTab);
+    harness.input.key(KeyCode::Enter);
+
+    assert_eq!(harness.run("(1)\n").unwrap(), command("print(1)"));
+    assert_eq!(harness.terminal.contents(), "> print(1)");
+}
+
+#[test]
+fn esc_cancels_completion_menu() {
+    let mut harness = Harness::<Keywords>::new(30, 6);
+    harness.input.type_str("p\t\t");
+    harness.input.key(KeyCode::Esc);
+
+    assert_eq!(harness.run("\n").unwrap(), command("pri"));
+    assert_eq!(harness.terminal.contents(), "> pri");
+}
+
+#[test]
+fn history_is_suggested() {
+    let mut harness = Harness::newd(30, 5);
+    harness.repl.set_suggestions(true);
+    harness.run("print 12\n").unwrap();
+    harness.run("print 3\n").unwrap();
+    harness.input.type_str("print 1");
+
+    assert!(harness.read().is_err());
+    assert_eq!(harness.terminal.row(2), "> print 12");
+    assert_eq!(harness.terminal.cell(9, 2).fg, Color::DarkGrey);
+    assert_eq!(harness.terminal.cursor(), (9, 2));
+
+    let mut harness = Harness::newd(30, 5);
+    harness.repl.set_suggestions(true);
+    harness.run("print 12\n").unwrap();
+    harness.input.type_str("pr");
+    harness.input.key(KeyCode::Right);
+
+    // The command is printed without a suggestion once it is given
+    assert_eq!(harness.run("3\n").unwrap(), command("print 123"));
+    assert_eq!(harness.terminal.contents(), "> print 12\n> print 123");
+}
+
+#[test]
+fn suggestions_are_off_by_default() {
+    let mut harness = Harness::newd(30, 5);
+    harness.run("print 12\n").unwrap();
+    harness.input.type_str("pr");
+    harness.input.key(KeyCode::Right);
+
+    assert_eq!(harness.run("\n").unwrap(), command("pr"));
+    assert_eq!(harness.terminal.contents(), "> print 12\n> pr");
+}
+
+#[test]
+fn multi_line_suggestion() {
+    let mut harness = Harness::<Braces>::new(30, 6);
+    harness.repl.set_suggestions(true);
+    harness.run("if x {\ny\n}\n").unwrap();
+    harness.input.type_str("if");
+
+    assert!(harness.read().is_err());
+    assert_eq!(
+        harness.terminal.rows()[3..],
+        ["> if x {", ".     y", ".     }"]
+    );
+    assert_eq!(harness.terminal.cursor(), (4, 3));
+
+    let mut harness = Harness::<Braces>::new(30, 6);
+    harness.repl.set_suggestions(true);
+    harness.run("if x {\ny\n}\n").unwrap();
+    harness.input.type_str("if");
+    harness
+        .input
+        .key_with(KeyCode::Char('f'), KeyModifiers::CONTROL);
+
+    assert_eq!(harness.run("\n").unwrap(), command("if x {\n    y\n    }"));
+}
+
+/// Suggests closing an open parenthesis
+struct Parens;
+
+impl LangInterface for Parens {
+    fn hint(lines: &[String], from_history: Option<String>) -> Option<String> {
+        let line = lines.last().unwrap();
+        if line.ends_with('(') {
+            Some(")".to_owned())
+        } else {
+            from_history
+        }
+    }
+}
+
+#[test]
+fn lang_interface_suggestion() {
+    let mut harness = Harness::<Parens>::new(30, 5);
+    harness.repl.set_suggestions(true);
+    harness.input.type_str("print(");
+    harness.input.key(KeyCode::End);
+
+    assert_eq!(harness.run("\n").unwrap(), command("print()"));
+
+    harness.repl.set_suggestions(false);
+    harness.input.type_str("prin");
+    harness.input.key(KeyCode::End);
+    assert_eq!(harness.run("\n").unwrap(), command("prin"));
+}
+
+#[test]
+fn reverse_search() {
+    let mut harness = Harness::<Braces>::new(40, 8);
+    harness.run("let a = 1\n").unwrap();
+    harness.run("if a {\nlet b = a\n}\n").unwrap();
+    harness.run("print a\n").unwrap();
+    let input = harness.input.clone();
+    let ctrl_r = || input.key_with(KeyCode::Char('r'), KeyModifiers::CONTROL);
+
+    ctrl_r();
+    harness.input.type_str("let");
+    assert!(harness.read().is_err());
+    // The match is found in the second line of the block, and highlighted
+    assert_eq!(
+        harness.terminal.rows()[5..],
+        [
+            "(reverse-i-search)`let': if a {",
+            ".     let b = a",
+            ".     }"
+        ]
+    );
+    assert_eq!(harness.terminal.cursor(), (6, 6));
+
+    let mut harness = Harness::<Braces>::new(40, 8);
+    harness.run("let a = 1\n").unwrap();
+    harness.run("if a {\nlet b = a\n}\n").unwrap();
+    harness.run("print a\n").unwrap();
+    let input = harness.input.clone();
+    let ctrl_r = || input.key_with(KeyCode::Char('r'), KeyModifiers::CONTROL);
+
+    // Ctrl-R again goes to an older match, and Enter puts it in the lines to be edited
+    ctrl_r();
+    harness.input.type_str("let");
+    ctrl_r();
+    harness.input.key(KeyCode::Enter);
+    harness.input.key(KeyCode::End);
+    assert_eq!(harness.run("0\n").unwrap(), command("let a = 10"));
+}
+
+#[test]
+fn failed_search_and_esc() {
+    let mut harness = Harness::newd(40, 5);
+    harness.run("print 1\n").unwrap();
+    let input = harness.input.clone();
+    let ctrl_r = || input.key_with(KeyCode::Char('r'), KeyModifiers::CONTROL);
+
+    harness.input.type_str("draft");
+    ctrl_r();
+    harness.input.type_str("pz");
+    assert!(harness.read().is_err());
+    assert_eq!(
+        harness.terminal.row(1),
+        "(failed reverse-i-search)`pz': print 1"
+    );
+
+    let mut harness = Harness::newd(40, 5);
+    harness.run("print 1\n").unwrap();
+    let input = harness.input.clone();
+    let ctrl_r = || input.key_with(KeyCode::Char('r'), KeyModifiers::CONTROL);
+
+    // Esc goes back to what was typed before searching
+    harness.input.type_str("draft");
+    ctrl_r();
+    harness.input.type_str("pr");
+    harness.input.key(KeyCode::Esc);
+    assert_eq!(harness.run("s\n").unwrap(), command("drafts"));
+    assert_eq!(harness.terminal.row(1), "> drafts");
+}
+
+#[test]
+fn prefix_history_navigation() {
+    let mut harness = Harness::newd(30, 8);
+    harness
+        .repl
+        .set_history_navigation(HistoryNavigation::Prefix);
+    harness.run("print 1\n").unwrap();
+    harness.run("let x\n").unwrap();
+    harness.run("print 2\n").unwrap();
+
+    harness.input.type_str("pr");
+    harness.input.key(KeyCode::Up);
+    harness.input.key(KeyCode::Up);
+    assert_eq!(harness.run("\n").unwrap(), command("print 1"));
+
+    // Going past the oldest match stays on it, and going back down shows what was typed
+    harness.input.type_str("pr");
+    for _ in 0..3 {
+        harness.input.key(KeyCode::Up);
+    }
+    harness.input.key(KeyCode::Down);
+    harness.input.key(KeyCode::Down);
+    harness.input.key(KeyCode::Down);
+    assert_eq!(harness.run("ompt\n").unwrap(), command("prompt"));
+
+    // Without anything typed, every entry is gone through
+    harness.input.key(KeyCode::Up);
+    harness.input.key(KeyCode::Up);
+    assert_eq!(harness.run("\n").unwrap(), command("print 1"));
+}
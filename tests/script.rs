@@ -0,0 +1,41 @@
+mod common;
+
+use common::Braces;
+use shelp::Commands;
+use std::io::Write;
+
+fn split(src: &str) -> Vec<String> {
+    Commands::<Braces, _>::new(src.as_bytes())
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+#[test]
+fn splits_on_complete_blocks() {
+    let src = "let a = 2\nif a {\n    a += 1\n\n}\n\n\nprint a";
+    assert_eq!(
+        split(src),
+        ["let a = 2", "if a {\n    a += 1\n\n}", "print a"]
+    );
+}
+
+#[test]
+fn unfinished_block_is_given() {
+    assert_eq!(split("a\r\nb {\r\n  c\r\n"), ["a", "b {\n  c"]);
+    assert!(split("\n  \n").is_empty());
+}
+
+#[test]
+fn reads_files() {
+    let path = std::env::temp_dir().join(format!("shelp-script-{}.rc", std::process::id()));
+    let mut file = std::fs::File::create(&path).unwrap();
+    file.write_all(b"x {\n}\ny\n").unwrap();
+
+    let commands: Vec<_> = Commands::<Braces>::open(&path)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(commands, ["x {\n}", "y"]);
+}
@@ -0,0 +1,173 @@
+mod common;
+
+use common::Braces;
+use crossterm::event::{KeyCode, KeyModifiers};
+use shelp::testing::Harness;
+use shelp::{EditMode, LangInterface, Signal};
+
+fn command(command: &str) -> Signal {
+    Signal::Command(command.to_owned())
+}
+
+fn harness<L: LangInterface>(width: u16, height: u16) -> Harness<L> {
+    let mut harness = Harness::new(width, height);
+    harness.repl.set_edit_mode(EditMode::Vi);
+    harness
+}
+
+/// Type `insert` in insert mode, then run `normal` in normal mode
+fn edit<L: LangInterface>(harness: &Harness<L>, insert: &str, normal: &str) {
+    harness.input.type_str(insert);
+    harness.input.key(KeyCode::Esc);
+    harness.input.type_str(normal);
+}
+
+#[test]
+fn mode_indicator() {
+    let mut harness = harness::<Braces>(30, 4);
+
+    edit(&harness, "if x {\ny\n}", "");
+    harness.input.key(KeyCode::Enter);
+    harness.read().unwrap();
+
+    assert_eq!(
+        harness.terminal.contents(),
+        "(cmd) > if x {\n.     y\n.     }"
+    );
+
+    edit(&harness, "abc", "");
+    harness
+        .input
+        .key_with(KeyCode::Char('c'), KeyModifiers::CONTROL);
+    assert_eq!(harness.read().unwrap(), Signal::Interrupted);
+    assert_eq!(harness.terminal.row(2), "(cmd) > abc^C");
+
+    harness.repl.set_mode_indicators("I ", "N ", "V ");
+    edit(&harness, "abc", "0");
+    assert!(harness.read().is_err());
+    // In normal mode the cursor is on a character, the first one here
+    assert_eq!(harness.terminal.row(3), "N > abc");
+    assert_eq!(harness.terminal.cursor(), (4, 3));
+}
+
+#[test]
+fn motions_and_operators() {
+    let mut harness = harness::<Braces>(40, 6);
+
+    edit(&harness, "one two three", "0dw");
+    assert_eq!(harness.run("\n").unwrap(), command("two three"));
+
+    edit(&harness, "foo.bar baz", "0cwqux");
+    assert_eq!(harness.run("\n").unwrap(), command("qux.bar baz"));
+
+    edit(&harness, "a,b,c,d", "02f,D");
+    assert_eq!(harness.run("\n").unwrap(), command("a,b"));
+
+    edit(&harness, "a(b, c)", "0dt)");
+    assert_eq!(harness.run("\n").unwrap(), command(")"));
+
+    edit(&harness, "one two three four", "02dw$yb0P");
+    assert_eq!(harness.run("\n").unwrap(), command("fouthree four"));
+}
+
+#[test]
+fn counts_and_repeat() {
+    let mut harness = harness::<Braces>(40, 6);
+
+    edit(&harness, "a b c d e f", "04x");
+    assert_eq!(harness.run("\n").unwrap(), command("c d e f"));
+
+    harness.input.type_str("foo bar baz");
+    harness.input.key(KeyCode::Esc);
+    harness.input.type_str("0cwqux");
+    harness.input.key(KeyCode::Esc);
+    harness.input.type_str("w.");
+    assert_eq!(harness.run("\n").unwrap(), command("qux qux baz"));
+
+    // The last change is kept across commands
+    edit(&harness, "one two", "0.");
+    assert_eq!(harness.run("\n").unwrap(), command("qux two"));
+
+    edit(&harness, "abcdef", "0x2.");
+    assert_eq!(harness.run("\n").unwrap(), command("def"));
+}
+
+#[test]
+fn oversized_counts() {
+    let mut harness = harness::<Braces>(40, 6);
+    let huge = "99999999999999999999";
+
+    edit(&harness, "a b c", &format!("$2d{}b", huge));
+    assert_eq!(harness.run("\n").unwrap(), command("c"));
+
+    edit(&harness, "abc", &format!("0{}rx", huge));
+    assert_eq!(harness.run("\n").unwrap(), command("abc"));
+
+    edit(&harness, "abc", &format!("0{}ddinew", huge));
+    assert_eq!(harness.run("\n").unwrap(), command("new"));
+
+    // Putting is limited to a sane number of copies
+    edit(&harness, "ab", &format!("0yl{}p", huge));
+    match harness.run("\n").unwrap() {
+        Signal::Command(command) => assert_eq!(command.len(), 2 + 9999),
+        signal => panic!("expected a command, got {:?}", signal),
+    }
+}
+
+#[test]
+fn multi_line_blocks() {
+    let mut harness = harness::<Braces>(40, 8);
+
+    // Words continue on the next line
+    edit(&harness, "if x {\nfoo", "ggwwwD");
+    assert_eq!(harness.run("A}\n").unwrap(), command("if x {\n    }"));
+
+    edit(&harness, "if x {\nfoo", "ggddGyyPJ");
+    harness.input.key(KeyCode::Enter);
+    assert_eq!(harness.read().unwrap(), command("    foo foo"));
+
+    edit(&harness, "if x {\nfoo", "kobar");
+    harness.input.key(KeyCode::Esc);
+    harness.input.type_str("GdkA}\n");
+    assert_eq!(harness.read().unwrap(), command("if x {}"));
+}
+
+#[test]
+fn visual_mode() {
+    let mut harness = harness::<Braces>(40, 8);
+
+    edit(&harness, "hello world", "0vey$p");
+    assert_eq!(harness.run("\n").unwrap(), command("hello worldhello"));
+
+    edit(&harness, "hello world", "0wvbd");
+    assert_eq!(harness.run("\n").unwrap(), command("orld"));
+
+    edit(&harness, "if x {\nfoo", "Vk~");
+    harness.input.type_str("Gccbar");
+    harness.input.key(KeyCode::Esc);
+    harness.input.type_str("A}\n");
+    assert_eq!(harness.read().unwrap(), command("IF X {\n    bar}"));
+}
+
+#[test]
+fn undo_and_history() {
+    let mut harness = harness::<Braces>(40, 8);
+
+    edit(&harness, "foo bar", "dbu");
+    harness
+        .input
+        .key_with(KeyCode::Char('r'), KeyModifiers::CONTROL);
+    harness.input.key(KeyCode::Enter);
+    assert_eq!(harness.read().unwrap(), command("foo r"));
+
+    edit(&harness, "draft", "kk");
+    harness.input.key(KeyCode::Enter);
+    assert_eq!(harness.read().unwrap(), command("foo r"));
+
+    // Changing a history entry leaves it as it was
+    edit(&harness, "", "kcwbaz");
+    harness.input.key(KeyCode::Esc);
+    harness.input.type_str("ju");
+    harness.input.key(KeyCode::Enter);
+    assert_eq!(harness.read().unwrap(), command("foo r"));
+}